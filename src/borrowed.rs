@@ -0,0 +1,354 @@
+//! A read-only view of an MSBT file that borrows from a byte slice instead of copying it.
+
+use crate::{
+  Header,
+  Msbt,
+  PADDING_CHAR,
+  error::{Error, Result},
+  section::lbl1::Label,
+};
+
+use byteordered::{Endian, Endianness};
+
+use std::io::Cursor;
+
+/// An MSBT file parsed in place from a byte slice.
+///
+/// The file's structure is validated when it is created, after which labels, strings and sections
+/// are returned as slices of the original bytes without allocating.
+#[derive(Debug)]
+pub struct MsbtRef<'a> {
+  data: &'a [u8],
+  header: Header,
+  lbl1: Option<SectionRef<'a>>,
+  txt2: Option<SectionRef<'a>>,
+}
+
+impl<'a> MsbtRef<'a> {
+  pub fn from_slice(data: &'a [u8]) -> Result<Self> {
+    let header = Header::from_reader(&mut &data[..])?;
+    let mut msbt = MsbtRef {
+      data,
+      header,
+      lbl1: None,
+      txt2: None,
+    };
+
+    for section in msbt.sections() {
+      let section = section?;
+      match &section.magic {
+        b"LBL1" => msbt.lbl1 = Some(section),
+        b"TXT2" => msbt.txt2 = Some(section),
+        _ => {},
+      }
+    }
+
+    if let Some(lbl1) = msbt.lbl1 {
+      msbt.validate_lbl1(lbl1)?;
+    }
+    if let Some(txt2) = msbt.txt2 {
+      msbt.validate_txt2(txt2)?;
+    }
+
+    Ok(msbt)
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  /// The bytes this view was parsed from.
+  pub fn data(&self) -> &'a [u8] {
+    self.data
+  }
+
+  pub fn sections(&self) -> Sections<'a> {
+    Sections {
+      data: self.data,
+      endianness: self.header.endianness,
+      pos: self.header.calc_file_size(),
+    }
+  }
+
+  /// The first section with the given magic, if any.
+  pub fn section(&self, magic: [u8; 4]) -> Option<SectionRef<'a>> {
+    // every section was read without errors when the view was created
+    self.sections().find_map(|s| s.ok().filter(|s| s.magic == magic))
+  }
+
+  pub fn lbl1(&self) -> Option<SectionRef<'a>> {
+    self.lbl1
+  }
+
+  pub fn txt2(&self) -> Option<SectionRef<'a>> {
+    self.txt2
+  }
+
+  /// All labels, in the order they are stored.
+  pub fn labels(&self) -> Labels<'a> {
+    let endianness = self.header.endianness;
+    let body = self.lbl1.map(|s| s.body).unwrap_or(&[]);
+    let group_count = u32_at(body, 0, endianness).unwrap_or(0);
+    Labels {
+      body,
+      endianness,
+      group_count,
+      group: 0,
+      remaining: 0,
+      pos: 0,
+    }
+  }
+
+  /// Finds a label by name, searching only its hash group.
+  pub fn label(&self, name: &str) -> Option<LabelRef<'a>> {
+    let endianness = self.header.endianness;
+    let body = self.lbl1?.body;
+    let group_count = u32_at(body, 0, endianness)?;
+    if group_count == 0 {
+      return None;
+    }
+    let group = Label::hash(name, group_count);
+    let mut labels = Labels {
+      body,
+      endianness,
+      group_count: group + 1,
+      group,
+      remaining: 0,
+      pos: 0,
+    };
+    labels.find(|l| l.name == name)
+  }
+
+  pub fn string_count(&self) -> u32 {
+    self.txt2
+      .and_then(|s| u32_at(s.body, 0, self.header.endianness))
+      .unwrap_or(0)
+  }
+
+  /// The raw bytes of the string at `index`, including its null terminator.
+  pub fn raw_string(&self, index: u32) -> Option<&'a [u8]> {
+    let endianness = self.header.endianness;
+    let body = self.txt2?.body;
+    let count = u32_at(body, 0, endianness)?;
+    if index >= count {
+      return None;
+    }
+    let start = u32_at(body, 4 + index as usize * 4, endianness)? as usize;
+    let end = if index + 1 == count {
+      body.len()
+    } else {
+      u32_at(body, 4 + (index as usize + 1) * 4, endianness)? as usize
+    };
+    body.get(start..end)
+  }
+
+  pub fn raw_strings(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+    (0..self.string_count()).filter_map(move |i| self.raw_string(i))
+  }
+
+  /// The raw string for the label `name`.
+  pub fn get_raw(&self, name: &str) -> Option<&'a [u8]> {
+    self.raw_string(self.label(name)?.index)
+  }
+
+  /// Copies this view into an owned, editable [`Msbt`].
+  pub fn to_owned(&self) -> Result<Msbt> {
+    Msbt::from_reader(Cursor::new(self.data))
+  }
+
+  fn validate_lbl1(&self, lbl1: SectionRef<'a>) -> Result<()> {
+    let invalid = || Error::InvalidSectionSize(lbl1.magic);
+    let endianness = self.header.endianness;
+    let group_count = u32_at(lbl1.body, 0, endianness).ok_or_else(invalid)?;
+    for group in 0..group_count as usize {
+      let label_count = u32_at(lbl1.body, 4 + group * 8, endianness).ok_or_else(invalid)?;
+      let mut pos = u32_at(lbl1.body, 8 + group * 8, endianness).ok_or_else(invalid)? as usize;
+      for _ in 0..label_count {
+        let len = *lbl1.body.get(pos).ok_or_else(invalid)? as usize;
+        let name = lbl1.body.get(pos + 1..pos + 1 + len).ok_or_else(invalid)?;
+        std::str::from_utf8(name).map_err(Error::InvalidBorrowedUtf8)?;
+        u32_at(lbl1.body, pos + 1 + len, endianness).ok_or_else(invalid)?;
+        pos += 1 + len + 4;
+      }
+    }
+    Ok(())
+  }
+
+  fn validate_txt2(&self, txt2: SectionRef<'a>) -> Result<()> {
+    let invalid = || Error::InvalidSectionSize(txt2.magic);
+    let endianness = self.header.endianness;
+    let count = u32_at(txt2.body, 0, endianness).ok_or_else(invalid)? as usize;
+    let mut last = 4 + count * 4;
+    for i in 0..count {
+      let offset = u32_at(txt2.body, 4 + i * 4, endianness).ok_or_else(invalid)? as usize;
+      if offset < last || offset > txt2.body.len() {
+        return Err(invalid());
+      }
+      last = offset;
+    }
+    Ok(())
+  }
+}
+
+fn u32_at(bytes: &[u8], pos: usize, endianness: Endianness) -> Option<u32> {
+  endianness.read_u32(bytes.get(pos..pos + 4)?).ok()
+}
+
+/// A section borrowed from an [`MsbtRef`].
+#[derive(Debug, Clone, Copy)]
+pub struct SectionRef<'a> {
+  magic: [u8; 4],
+  body: &'a [u8],
+}
+
+impl<'a> SectionRef<'a> {
+  pub fn magic(&self) -> [u8; 4] {
+    self.magic
+  }
+
+  /// The section's data, after its 16-byte header and without padding.
+  pub fn body(&self) -> &'a [u8] {
+    self.body
+  }
+}
+
+/// An iterator over the sections of an [`MsbtRef`].
+///
+/// A section that cannot be read is returned as an error, after which the iterator ends.
+#[derive(Debug, Clone)]
+pub struct Sections<'a> {
+  data: &'a [u8],
+  endianness: Endianness,
+  pos: usize,
+}
+
+impl<'a> Sections<'a> {
+  fn next_section(&mut self) -> Result<Option<SectionRef<'a>>> {
+    while self.data.get(self.pos) == Some(&PADDING_CHAR) {
+      self.pos += 1;
+    }
+    if self.pos >= self.data.len() {
+      return Ok(None);
+    }
+
+    let header = self.data
+      .get(self.pos..self.pos + 16)
+      .ok_or_else(|| Error::Io(std::io::ErrorKind::UnexpectedEof.into()))?;
+    let mut magic = [0; 4];
+    magic.copy_from_slice(&header[..4]);
+    let size = self.endianness.read_u32(&header[4..8]).map_err(Error::Io)? as usize;
+    let start = self.pos + 16;
+    let body = self.data.get(start..start + size).ok_or(Error::InvalidSectionSize(magic))?;
+    self.pos = start + size;
+
+    Ok(Some(SectionRef { magic, body }))
+  }
+}
+
+impl<'a> Iterator for Sections<'a> {
+  type Item = Result<SectionRef<'a>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let section = self.next_section();
+    if section.is_err() {
+      self.pos = self.data.len();
+    }
+    section.transpose()
+  }
+}
+
+/// A label borrowed from an [`MsbtRef`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelRef<'a> {
+  name: &'a str,
+  index: u32,
+  checksum: u32,
+}
+
+impl<'a> LabelRef<'a> {
+  pub fn name(&self) -> &'a str {
+    self.name
+  }
+
+  /// The index of this label's string in TXT2.
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  /// The hash group this label is stored in.
+  pub fn checksum(&self) -> u32 {
+    self.checksum
+  }
+}
+
+/// An iterator over the labels of an [`MsbtRef`].
+#[derive(Debug, Clone)]
+pub struct Labels<'a> {
+  body: &'a [u8],
+  endianness: Endianness,
+  group_count: u32,
+  group: u32,
+  remaining: u32,
+  pos: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+  type Item = LabelRef<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.remaining == 0 {
+      if self.group >= self.group_count {
+        return None;
+      }
+      let group_pos = 4 + self.group as usize * 8;
+      self.remaining = u32_at(self.body, group_pos, self.endianness)?;
+      self.pos = u32_at(self.body, group_pos + 4, self.endianness)? as usize;
+      self.group += 1;
+    }
+
+    let len = *self.body.get(self.pos)? as usize;
+    let name = std::str::from_utf8(self.body.get(self.pos + 1..self.pos + 1 + len)?).ok()?;
+    let index = u32_at(self.body, self.pos + 1 + len, self.endianness)?;
+    self.pos += 1 + len + 4;
+    self.remaining -= 1;
+
+    Some(LabelRef {
+      name,
+      index,
+      checksum: self.group - 1,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::sample;
+
+  #[test]
+  fn iterates_sections() {
+    let bytes = sample();
+    let msbt = MsbtRef::from_slice(&bytes).unwrap();
+    let magics = msbt.sections().map(|s| s.map(|s| s.magic())).collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(magics, vec![*b"LBL1", *b"TXT2"]);
+    assert_eq!(msbt.section(*b"TXT2").map(|s| s.body()), msbt.txt2().map(|s| s.body()));
+    assert_eq!(msbt.get_raw("farewell"), Some(&[66, 0, 121, 0, 101, 0, 0, 0][..]));
+  }
+
+  #[test]
+  fn stops_at_the_first_error() {
+    let bytes = sample();
+    let txt2 = bytes.windows(4).position(|w| w == b"TXT2").unwrap();
+    let truncated = &bytes[..txt2 + 20];
+    assert!(matches!(MsbtRef::from_slice(truncated), Err(Error::InvalidSectionSize(magic)) if &magic == b"TXT2"));
+
+    let mut sections = Sections {
+      data: truncated,
+      endianness: Endianness::Little,
+      pos: 0x20,
+    };
+    assert_eq!(sections.next().unwrap().unwrap().magic(), *b"LBL1");
+    assert!(sections.next().unwrap().is_err());
+    assert!(sections.next().is_none());
+  }
+}
@@ -3,93 +3,71 @@ use crate::{
   Header,
   Msbt,
   SectionTag,
-  section::*,
+  error::{Error, Result},
+  message,
+  msbp::{Msbp, ati2::AttributeDef},
+  section::{
+    *,
+    atr1::AttributeValue,
+    lbl1::Group,
+  },
   traits::Updates,
 };
 
 use byteordered::Endianness;
 
-use std::{
-  boxed::Box,
-  pin::Pin,
-  ptr::NonNull,
-};
-
+/// Builds an [`Msbt`] from messages and sections.
+///
+/// Messages are added with [`message`](MsbtBuilder::message) or
+/// [`add_label`](MsbtBuilder::add_label), after any strings in a TXT2 given with
+/// [`txt2`](MsbtBuilder::txt2). [`style`](MsbtBuilder::style),
+/// [`attributes`](MsbtBuilder::attributes), [`attribute`](MsbtBuilder::attribute) and
+/// [`global_id`](MsbtBuilder::global_id) apply to the message added last, creating TSY1, ATR1 and
+/// NLI1 when they are needed.
+///
+/// Problems are reported by [`build`](MsbtBuilder::build).
 pub struct MsbtBuilder {
   section_order: Vec<SectionTag>,
   header: Header,
-  lbl1: Option<Pin<Box<Lbl1>>>,
+  lbl1: Option<Lbl1>,
   txt2: Option<Txt2>,
   nli1: Option<Nli1>,
   ato1: Option<Ato1>,
   atr1: Option<Atr1>,
   tsy1: Option<Tsy1>,
+  unknown_sections: Vec<UnknownSection>,
+  messages: Vec<BuilderMessage>,
+  error: Option<Error>,
 }
 
-  // macro_rules! add_item {
-  //   ($lower:ident, $upper:ident) => {
-  //     pub fn $lower(mut self, $lower: $upper) -> Self {
-  //       self.section_order.remove_item(&SectionTag::$upper);
-  //       self.section_order.push(SectionTag::$upper);
-  //       self.$lower = Some($lower);
-
-  //       self
-  //     }
-  //   };
-  // }
+#[derive(Debug, Default)]
+struct BuilderMessage {
+  name: String,
+  value: Vec<u8>,
+  style: Option<u32>,
+  record: Option<Vec<u8>>,
+  attributes: Vec<(AttributeDef, AttributeValue)>,
+  global_id: Option<u32>,
+}
 
 impl MsbtBuilder {
   pub fn new(endianness: Endianness, encoding: Encoding, group_count: Option<u32>) -> Self {
-    let lbl1 = group_count
-      .map(|gc| {
-        let groups = (0..gc)
-          .map(|_| crate::section::lbl1::Group {
-            label_count: 0,
-            offset: 0,
-          })
-          .collect();
-        Lbl1 {
-          msbt: NonNull::dangling(),
-          section: Section::new(*b"LBL1", 0),
-          group_count: gc,
-          groups,
-          labels: Vec::with_capacity(gc as usize),
-        }
-      })
-      .map(Box::pin);
-    let txt2 = group_count.map(|_| Txt2 {
-      msbt: NonNull::dangling(),
-      section: Section::new(*b"TXT2", 0),
-      string_count: 0,
-      raw_strings: Vec::new(),
-    });
-    let (section_count, section_order) = if group_count.is_some() {
-      let mut order = Vec::with_capacity(6);
-      order.push(SectionTag::Lbl1);
-      order.push(SectionTag::Txt2);
-      (2, order)
-    } else {
-      (0, Vec::with_capacity(6))
-    };
-    MsbtBuilder {
-      section_order,
-      header: Header {
-        magic: crate::HEADER_MAGIC,
-        endianness,
-        _unknown_1: 0,
-        encoding,
-        _unknown_2: 3,
-        section_count,
-        _unknown_3: 0,
-        file_size: 0,
-        padding: [0; 10],
-      },
-      lbl1,
-      txt2,
+    let builder = MsbtBuilder {
+      section_order: Vec::with_capacity(6),
+      header: Header::new(endianness, encoding),
+      lbl1: None,
+      txt2: None,
       nli1: None,
       ato1: None,
       atr1: None,
       tsy1: None,
+      unknown_sections: Vec::new(),
+      messages: Vec::new(),
+      error: None,
+    };
+    match group_count {
+      Some(group_count) => builder.group_count(group_count),
+      None => builder,
     }
   }
 
@@ -97,8 +75,36 @@ impl MsbtBuilder {
     &self.header
   }
 
-  pub fn build(self) -> Pin<Box<Msbt>> {
-    let msbt = Msbt {
+  /// Sets the number of LBL1 hash groups, adding LBL1 and TXT2 if they are missing.
+  pub fn group_count(mut self, group_count: u32) -> Self {
+    if group_count == 0 {
+      return self.fail(Error::InvalidGroupCount(group_count));
+    }
+    match self.lbl1.as_mut() {
+      Some(lbl1) => if let Err(e) = lbl1.rehash(group_count) {
+        return self.fail(e);
+      },
+      None => {
+        let groups = vec![Group { label_count: 0, offset: 0 }; group_count as usize];
+        self.lbl1 = Some(Lbl1::new(Section::new(*b"LBL1", 0), group_count, groups, Vec::new()));
+        self.section_order.insert(0, SectionTag::Lbl1);
+      },
+    }
+    if self.txt2.is_none() {
+      self.txt2 = Some(Txt2::empty());
+      self.section_order.push(SectionTag::Txt2);
+    }
+
+    self
+  }
+
+  /// Builds the file, adding every message to its sections.
+  pub fn build(self) -> Result<Msbt> {
+    if let Some(e) = self.error {
+      return Err(e);
+    }
+
+    let mut msbt = Msbt {
       header: self.header,
       section_order: self.section_order,
       lbl1: self.lbl1,
@@ -107,97 +113,126 @@ impl MsbtBuilder {
       atr1: self.atr1,
       tsy1: self.tsy1,
       txt2: self.txt2,
+      unknown_sections: self.unknown_sections,
     };
-    let mut pinned_msbt = Box::pin(msbt);
+    msbt.update();
 
-    let msbt_ref: &mut Msbt = unsafe {
-      let mut_ref: Pin<&mut Msbt> = Pin::as_mut(&mut pinned_msbt);
-      Pin::get_unchecked_mut(mut_ref)
-    };
-    let ptr = NonNull::new(msbt_ref as *mut Msbt).unwrap();
-    if let Some(mut atr1) = msbt_ref.atr1.as_mut() {
-      atr1.msbt = ptr;
-      atr1.update();
-    }
-    if let Some(lbl1) = msbt_ref.lbl1.as_mut() {
-      lbl1.msbt = ptr;
-      lbl1.update();
-    }
-    if let Some(mut nli1) = msbt_ref.nli1.as_mut() {
-      nli1.msbt = ptr;
-    }
-    if let Some(mut ato1) = msbt_ref.ato1.as_mut() {
-      ato1.msbt = ptr;
+    let first = msbt.txt2.as_ref().map(|t| t.string_count()).unwrap_or(0);
+    let mut messages = self.messages;
+    msbt.insert_messages_raw(messages
+      .iter_mut()
+      .map(|m| (std::mem::take(&mut m.name), std::mem::take(&mut m.value))))?;
+
+    let entry_size = messages
+      .iter()
+      .flat_map(|m| m.record.iter().map(Vec::len).chain(m.attributes
+        .iter()
+        .map(|(def, _)| def.offset as usize + Atr1::value_size(def.value_type))))
+      .max();
+    if let Some(entry_size) = entry_size {
+      if msbt.atr1.is_none() {
+        let records = vec![vec![0; entry_size]; (first as usize) + messages.len()];
+        msbt.atr1 = Some(Atr1::new_unlinked(entry_size as u32, records, Vec::new())?);
+        MsbtBuilder::insert_tag(&mut msbt.section_order, SectionTag::Atr1);
+      }
     }
-    if let Some(mut tsy1) = msbt_ref.tsy1.as_mut() {
-      tsy1.msbt = ptr;
+    if messages.iter().any(|m| m.style.is_some()) && msbt.tsy1.is_none() {
+      msbt.tsy1 = Some(Tsy1::new_unlinked(vec![0; (first as usize) + messages.len()]));
+      MsbtBuilder::insert_tag(&mut msbt.section_order, SectionTag::Tsy1);
     }
-    if let Some(mut txt2) = msbt_ref.txt2.as_mut() {
-      txt2.msbt = ptr;
-      txt2.update();
+    if messages.iter().any(|m| m.global_id.is_some()) && msbt.nli1.is_none() {
+      msbt.nli1 = Some(Nli1::new_unlinked(0, Default::default()));
+      MsbtBuilder::insert_tag(&mut msbt.section_order, SectionTag::Nli1);
     }
 
-    pinned_msbt.update();
+    for (i, message) in messages.into_iter().enumerate() {
+      let index = first as usize + i;
+      if let (Some(style), Some(tsy1)) = (message.style, msbt.tsy1.as_mut()) {
+        tsy1.set_style(index, style)?;
+      }
+      if let Some(atr1) = msbt.atr1.as_mut() {
+        if let Some(record) = message.record {
+          atr1.set_record(index, record)?;
+        }
+        for (def, value) in message.attributes {
+          atr1.encode_attribute(index, &def, value, &msbt.header)?;
+        }
+      }
+      if let (Some(id), Some(nli1)) = (message.global_id, msbt.nli1.as_mut()) {
+        nli1.global_ids.insert(index as u32, id);
+      }
+    }
 
-    pinned_msbt
+    msbt.update();
+    Ok(msbt)
   }
 
-  pub fn add_label<N: Into<String>, V: Into<Vec<u8>>>(mut self, name: N, value: V) -> Self {
-    let name = name.into();
-    let value = value.into();
-
-    let lbl1 = match self.lbl1.as_mut() {
-      Some(l) => l,
-      None => return self,
-    };
-    let txt2 = match self.txt2.as_mut() {
-      Some(l) => l,
-      None => return self,
-    };
+  /// Adds a message, encoding `text` in the builder's encoding and appending a null terminator.
+  pub fn message<N: Into<String>>(mut self, name: N, text: &str) -> Self {
+    let value = message::encode_message(text, self.header.encoding, self.header.endianness);
+    self.messages.push(BuilderMessage {
+      name: name.into(),
+      value,
+      ..Default::default()
+    });
 
-    let label = crate::section::lbl1::Label {
-      lbl1: {
-        let lbl1_ref: &mut Lbl1 = unsafe {
-          let mut_ref: Pin<&mut Lbl1> = Pin::as_mut(lbl1);
-          Pin::get_unchecked_mut(mut_ref)
-        };
-        NonNull::new(lbl1_ref as *mut Lbl1).unwrap()
-      },
-      checksum: name.as_bytes()
-        .iter()
-        .fold(0u32, |hash, b| hash
-          .overflowing_mul(crate::section::lbl1::Label::HASH_MAGIC).0
-          .overflowing_add(u32::from(*b)).0) % lbl1.group_count,
-      name,
-      index: txt2.raw_strings().len() as u32,
-    };
+    self
+  }
 
-    txt2.raw_strings.push(value);
-    lbl1.groups[label.checksum as usize].label_count += 1;
-    lbl1.labels.push(label);
+  /// Adds a message with a raw value, which should include its null terminator.
+  pub fn add_label<N: Into<String>, V: Into<Vec<u8>>>(mut self, name: N, value: V) -> Self {
+    self.messages.push(BuilderMessage {
+      name: name.into(),
+      value: value.into(),
+      ..Default::default()
+    });
 
     self
   }
 
-  // pub fn lbl1(mut self, lbl1: Lbl1) -> Self {
-  //   self.section_order.remove_item(&SectionTag::Lbl1);
-  //   self.section_order.push(SectionTag::Lbl1);
-
-  //   let mut pinned_lbl1 = Box::pin(lbl1);
+  /// Sets the TSY1 style of the last message.
+  pub fn style(self, style: u32) -> Self {
+    self.with_last(|m| {
+      m.style = Some(style);
+      Ok(())
+    })
+  }
 
-  //   let lbl1_ref: &mut Lbl1 = unsafe {
-  //     let mut_ref: Pin<&mut Lbl1> = Pin::as_mut(&mut pinned_lbl1);
-  //     Pin::get_unchecked_mut(mut_ref)
-  //   };
-  //   let ptr = NonNull::new(lbl1_ref as *mut Lbl1).unwrap();
-  //   for mut label in &mut lbl1_ref.labels {
-  //     label.lbl1 = ptr;
-  //   }
+  /// Sets the raw ATR1 record of the last message.
+  pub fn attributes<R: Into<Vec<u8>>>(self, record: R) -> Self {
+    let record = record.into();
+    self.with_last(|m| {
+      m.record = Some(record);
+      Ok(())
+    })
+  }
 
-  //   self.lbl1 = Some(pinned_lbl1);
+  /// Sets a named attribute of the last message, using the attribute definitions in `msbp`.
+  ///
+  /// This is applied after any raw record set with [`attributes`](MsbtBuilder::attributes). Each
+  /// attribute can only be set once per message.
+  pub fn attribute(self, msbp: &Msbp, name: &str, value: AttributeValue) -> Self {
+    let def = Atr1::find_def(msbp, name);
+    self.with_last(|m| {
+      let def = def?;
+      if m.attributes.iter().any(|(d, _)| d.name == def.name) {
+        return Err(Error::InvalidAttribute {
+          name: def.name,
+          reason: "set more than once".to_string(),
+        });
+      }
+      m.attributes.push((def, value));
+      Ok(())
+    })
+  }
 
-  //   self
-  // }
+  /// Sets the NLI1 global id of the last message.
+  pub fn global_id(self, id: u32) -> Self {
+    self.with_last(|m| {
+      m.global_id = Some(id);
+      Ok(())
+    })
+  }
 
   pub fn nli1(mut self, nli1: Nli1) -> Self {
     if let Some(pos) = self.section_order.iter().position(|x| x == &SectionTag::Nli1) {
@@ -248,15 +283,120 @@ impl MsbtBuilder {
 
     self
   }
+
+  /// Adds a section this crate does not otherwise support, written as-is after the sections
+  /// added so far.
+  pub fn unknown_section(mut self, section: UnknownSection) -> Self {
+    self.section_order.push(SectionTag::Unknown(self.unknown_sections.len()));
+    self.unknown_sections.push(section);
+
+    self
+  }
+
+  fn with_last<F>(mut self, f: F) -> Self
+    where F: FnOnce(&mut BuilderMessage) -> Result<()>,
+  {
+    let result = match self.messages.last_mut() {
+      Some(message) => f(message),
+      None => Err(Error::NoMessage),
+    };
+    match result {
+      Ok(()) => self,
+      Err(e) => self.fail(e),
+    }
+  }
+
+  // keeps the first error, which is returned by build
+  fn fail(mut self, error: Error) -> Self {
+    self.error.get_or_insert(error);
+    self
+  }
+
+  // sections created for messages are placed in the order they usually appear in
+  fn insert_tag(section_order: &mut Vec<SectionTag>, tag: SectionTag) {
+    const ORDER: [SectionTag; 6] = [
+      SectionTag::Lbl1,
+      SectionTag::Nli1,
+      SectionTag::Ato1,
+      SectionTag::Atr1,
+      SectionTag::Tsy1,
+      SectionTag::Txt2,
+    ];
+    let rank = |tag: &SectionTag| ORDER.iter().position(|x| x == tag).unwrap_or(ORDER.len());
+    match section_order.iter().position(|x| rank(x) > rank(&tag)) {
+      Some(pos) => section_order.insert(pos, tag),
+      None => section_order.push(tag),
+    }
+  }
 }
 
-    // let mut pinned_lbl1 = Box::pin(lbl1);
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::msbp::{ValueType, builder::MsbpBuilder};
+
+  use std::io::Cursor;
+
+  fn msbp() -> Msbp {
+    MsbpBuilder::new(Endianness::Little, 1)
+      .attribute("speaker", ValueType::String, 0)
+      .attribute("voice", ValueType::U32, 4)
+      .build()
+      .unwrap()
+  }
 
-    // let lbl1_ref: &mut Lbl1 = unsafe {
-    //   let mut_ref: Pin<&mut Lbl1> = Pin::as_mut(&mut pinned_lbl1);
-    //   Pin::get_unchecked_mut(mut_ref)
-    // };
-    // let ptr = NonNull::new(lbl1_ref as *mut Lbl1).unwrap();
-    // for mut label in &mut lbl1_ref.labels {
-    //   label.lbl1 = ptr;
-    // }
+  fn speaker(name: &str) -> AttributeValue {
+    AttributeValue::String(name.to_string())
+  }
+
+  #[test]
+  fn builds_string_attributes() {
+    let msbp = msbp();
+    let msbt = MsbtBuilder::new(Endianness::Big, Encoding::Utf16, Some(1))
+      .message("a", "A")
+      .attribute(&msbp, "speaker", speaker("Link"))
+      .message("b", "B")
+      .attribute(&msbp, "speaker", speaker("Zelda"))
+      .attribute(&msbp, "voice", AttributeValue::U32(7))
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+
+    let msbt = Msbt::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(msbt.attribute(0, &msbp, "speaker").unwrap(), speaker("Link"));
+    assert_eq!(msbt.attribute(1, &msbp, "speaker").unwrap(), speaker("Zelda"));
+    assert_eq!(msbt.attribute(1, &msbp, "voice").unwrap(), AttributeValue::U32(7));
+  }
+
+  #[test]
+  fn keeps_strings_of_given_atr1() {
+    let msbp = msbp();
+    let base = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(1))
+      .message("a", "A")
+      .attribute(&msbp, "speaker", speaker("Link"))
+      .build()
+      .unwrap();
+
+    let msbt = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(1))
+      .txt2(base.txt2().unwrap().clone())
+      .atr1(base.atr1().unwrap().clone())
+      .message("b", "B")
+      .attribute(&msbp, "speaker", speaker("Zelda"))
+      .build()
+      .unwrap();
+    assert_eq!(msbt.attribute(0, &msbp, "speaker").unwrap(), speaker("Link"));
+    assert_eq!(msbt.attribute(1, &msbp, "speaker").unwrap(), speaker("Zelda"));
+  }
+
+  #[test]
+  fn rejects_repeated_attributes() {
+    let msbp = msbp();
+    let result = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(1))
+      .message("a", "A")
+      .attribute(&msbp, "voice", AttributeValue::U32(1))
+      .attribute(&msbp, "voice", AttributeValue::U32(2))
+      .build();
+    assert!(matches!(result, Err(Error::InvalidAttribute { ref name, .. }) if name == "voice"));
+  }
+}
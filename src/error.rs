@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use std::fmt::{self, Display, Formatter};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
@@ -18,6 +20,178 @@ pub enum Error {
   InvalidBorrowedUtf8(std::str::Utf8Error),
   #[error("invalid utf-16: {0}")]
   InvalidUtf16(std::string::FromUtf16Error),
+  #[error("invalid utf-32 code point: {0:#x}")]
+  InvalidUtf32(u32),
   #[error("invalid section header: {0:?}")]
   InvalidSection([u8; 4]),
+  #[error("unexpected end of message at byte {0}")]
+  UnexpectedEndOfMessage(usize),
+  #[error("text cannot contain {0:?}")]
+  InvalidTextChar(char),
+  #[error("tag parameters too long: {0} bytes")]
+  TagParamsTooLong(usize),
+  #[error("no TXT2 string at index {0}")]
+  NoSuchString(u32),
+  #[error("invalid markup at line {line}, column {column}: {reason}")]
+  InvalidMarkup {
+    line: usize,
+    column: usize,
+    reason: String,
+  },
+  #[error("invalid parameter {param} for tag {tag}: {reason}")]
+  InvalidTagParam {
+    tag: String,
+    param: String,
+    reason: String,
+  },
+  #[error("unknown tag group: {0}")]
+  UnknownTagGroup(u16),
+  #[error("section {0:?} is too small for its contents")]
+  InvalidSectionSize([u8; 4]),
+  #[error("section {0:?} is too large to write")]
+  SectionTooLarge([u8; 4]),
+  #[error("file is too large to write: {0} bytes")]
+  FileTooLarge(u64),
+  #[error("invalid record size: {0} bytes")]
+  InvalidRecordSize(usize),
+  #[error("no record at index {0}")]
+  NoSuchRecord(usize),
+  #[error("no entry at index {0}")]
+  NoSuchEntry(usize),
+  #[error("invalid string offset: {0}")]
+  InvalidStringOffset(u32),
+  #[error("no label named {0}")]
+  NoSuchLabel(String),
+  #[error("label {0} already exists")]
+  DuplicateLabel(String),
+  #[error("invalid label hash group count: {0}")]
+  InvalidGroupCount(u32),
+  #[error("no message has been added yet")]
+  NoMessage,
+  #[error("missing section: {0:?}")]
+  MissingSection([u8; 4]),
+  #[error("section {0:?} is in the section order more than once")]
+  DuplicateSection([u8; 4]),
+  #[error("section {0:?} is not in the section order")]
+  UnorderedSection([u8; 4]),
+  #[error("label is longer than 255 bytes: {0}")]
+  LabelTooLong(String),
+  #[error("expected {expected} messages, but got {actual}")]
+  MessageCountMismatch {
+    expected: u32,
+    actual: u32,
+  },
+  #[error("unknown style: {0}")]
+  UnknownStyle(String),
+  #[error("unknown attribute: {0}")]
+  UnknownAttribute(String),
+  #[error("invalid attribute {name}: {reason}")]
+  InvalidAttribute {
+    name: String,
+    reason: String,
+  },
+  #[error("{context}: {source}")]
+  Context {
+    context: ErrorContext,
+    source: Box<Error>,
+  },
+}
+
+impl Error {
+  /// Where in the file the error happened, if known.
+  pub fn context(&self) -> Option<&ErrorContext> {
+    match *self {
+      Error::Context { ref context, .. } => Some(context),
+      _ => None,
+    }
+  }
+
+  /// The underlying error, without its context.
+  pub fn inner(&self) -> &Error {
+    match *self {
+      Error::Context { ref source, .. } => source.inner(),
+      ref e => e,
+    }
+  }
+
+  /// Adds context to this error, keeping any more specific context it already has.
+  pub(crate) fn with_context(self, context: ErrorContext) -> Error {
+    match self {
+      Error::Context { context: existing, source } => Error::Context {
+        context: ErrorContext {
+          offset: existing.offset.or(context.offset),
+          section: existing.section.or(context.section),
+          item: existing.item.or(context.item),
+        },
+        source,
+      },
+      e => Error::Context {
+        context,
+        source: Box::new(e),
+      },
+    }
+  }
+}
+
+/// The location of an error within a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+  /// The absolute byte offset the reader had reached.
+  pub offset: Option<u64>,
+  /// The magic of the section being parsed.
+  pub section: Option<[u8; 4]>,
+  /// The item within the section being parsed.
+  pub item: Option<ErrorItem>,
+}
+
+impl Display for ErrorContext {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let mut parts = Vec::with_capacity(3);
+    if let Some(offset) = self.offset {
+      parts.push(format!("at offset {:#x}", offset));
+    }
+    if let Some(section) = self.section {
+      parts.push(format!("in section {}", String::from_utf8_lossy(&section)));
+    }
+    if let Some(item) = self.item {
+      parts.push(item.to_string());
+    }
+    if parts.is_empty() {
+      return f.write_str("at an unknown location");
+    }
+    f.write_str(&parts.join(", "))
+  }
+}
+
+/// An item within a section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorItem {
+  /// A label hash group.
+  Group(u32),
+  /// The `index`th label of hash group `group`.
+  Label {
+    group: u32,
+    index: u32,
+  },
+  /// A TXT2 string.
+  String(u32),
+  /// An ATR1 record.
+  Record(u32),
+  /// An entry of a table section such as ATO1, TSY1 or NLI1.
+  Entry(u32),
+  /// The header of the `n`th section in the file.
+  SectionHeader(u32),
+}
+
+impl Display for ErrorItem {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match *self {
+      ErrorItem::Group(group) => write!(f, "group {}", group),
+      ErrorItem::Label { group, index } => write!(f, "label {} in group {}", index, group),
+      ErrorItem::String(index) => write!(f, "string {}", index),
+      ErrorItem::Record(index) => write!(f, "record {}", index),
+      ErrorItem::Entry(index) => write!(f, "entry {}", index),
+      ErrorItem::SectionHeader(index) => write!(f, "header of section {}", index),
+    }
+  }
 }
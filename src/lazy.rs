@@ -0,0 +1,299 @@
+//! A reader that indexes an MSBT file up front and reads labels and strings only when asked for.
+
+use crate::{
+  Header,
+  PADDING_CHAR,
+  error::{Error, Result},
+  message,
+  section::{
+    Section,
+    lbl1::{Group, Label},
+  },
+  traits::CalculatesSize,
+};
+
+use byteordered::Endian;
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// An MSBT file that is read on demand.
+///
+/// Creating one reads the header, the position of every section, the LBL1 hash groups and the TXT2
+/// string offsets. Labels are looked up by reading only their hash group, and strings are read
+/// when they are accessed.
+///
+/// The file may start anywhere in the reader, such as inside an archive: its start is the
+/// reader's position when it is passed to [`new`](LazyMsbt::new).
+#[derive(Debug)]
+pub struct LazyMsbt<R> {
+  reader: R,
+  header: Header,
+  sections: Vec<SectionIndex>,
+  groups: Vec<Group>,
+  lbl1_offset: u64,
+  // absolute start and end of each string
+  strings: Vec<(u64, u64)>,
+}
+
+/// The location of a section within a [`LazyMsbt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionIndex {
+  pub magic: [u8; 4],
+  /// The absolute position of the section's data, after its header.
+  pub offset: u64,
+  pub size: u32,
+}
+
+impl<R: Read + Seek> LazyMsbt<R> {
+  pub fn new(mut reader: R) -> Result<Self> {
+    let start = reader.stream_position().map_err(Error::Io)?;
+    let header = Header::from_reader(&mut reader)?;
+    let end = reader.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+    let end = end.min(start + u64::from(header.file_size));
+
+    let mut msbt = LazyMsbt {
+      reader,
+      header,
+      sections: Vec::new(),
+      groups: Vec::new(),
+      lbl1_offset: 0,
+      strings: Vec::new(),
+    };
+    msbt.index_sections(start + msbt.header.calc_file_size() as u64, end)?;
+    msbt.index_lbl1()?;
+    msbt.index_txt2()?;
+
+    Ok(msbt)
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  pub fn sections(&self) -> &[SectionIndex] {
+    &self.sections
+  }
+
+  pub fn section(&self, magic: [u8; 4]) -> Option<&SectionIndex> {
+    self.sections.iter().find(|s| s.magic == magic)
+  }
+
+  /// Reads the data of the first section with the given magic.
+  pub fn section_bytes(&mut self, magic: [u8; 4]) -> Result<Option<Vec<u8>>> {
+    let section = match self.section(magic) {
+      Some(s) => *s,
+      None => return Ok(None),
+    };
+    self.read_at(section.offset, u64::from(section.size)).map(Some)
+  }
+
+  pub fn string_count(&self) -> u32 {
+    self.strings.len() as u32
+  }
+
+  /// Finds the TXT2 index of the label `name`, reading only its hash group.
+  pub fn label_index(&mut self, name: &str) -> Result<Option<u32>> {
+    if self.groups.is_empty() {
+      return Ok(None);
+    }
+    let group = &self.groups[Label::hash(name, self.groups.len() as u32) as usize];
+    let (label_count, offset) = (group.label_count, group.offset);
+
+    self.reader.seek(SeekFrom::Start(self.lbl1_offset + u64::from(offset))).map_err(Error::Io)?;
+    let mut buf = [0; 255];
+    for _ in 0..label_count {
+      self.reader.read_exact(&mut buf[..1]).map_err(Error::Io)?;
+      let len = buf[0] as usize;
+      self.reader.read_exact(&mut buf[..len]).map_err(Error::Io)?;
+      let index = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+      if &buf[..len] == name.as_bytes() {
+        return Ok(Some(index));
+      }
+    }
+
+    Ok(None)
+  }
+
+  /// Reads the raw bytes of the string at `index`, including its null terminator.
+  pub fn raw_string(&mut self, index: u32) -> Result<Vec<u8>> {
+    let (start, end) = *self.strings.get(index as usize).ok_or(Error::NoSuchString(index))?;
+    self.read_at(start, end - start)
+  }
+
+  /// Reads and decodes the string at `index`, without its null terminator.
+  pub fn string(&mut self, index: u32) -> Result<String> {
+    let raw = self.raw_string(index)?;
+    let raw = message::strip_terminator(&raw, self.header.encoding);
+    message::decode_string(raw, self.header.encoding, self.header.endianness)
+  }
+
+  /// Reads the raw string for the label `name`.
+  pub fn get_raw(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+    match self.label_index(name)? {
+      Some(index) => self.raw_string(index).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  /// Reads and decodes the string for the label `name`.
+  pub fn get(&mut self, name: &str) -> Result<Option<String>> {
+    match self.label_index(name)? {
+      Some(index) => self.string(index).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  pub fn into_inner(self) -> R {
+    self.reader
+  }
+
+  fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+    self.reader.seek(SeekFrom::Start(offset)).map_err(Error::Io)?;
+    let mut buf = vec![0; len as usize];
+    self.reader.read_exact(&mut buf).map_err(Error::Io)?;
+    Ok(buf)
+  }
+
+  fn index_sections(&mut self, mut pos: u64, end: u64) -> Result<()> {
+    let mut buf = [0; 16];
+    // look past the end for sections the header counts, so that they are reported rather than
+    // silently left out
+    while pos < end || self.sections.len() < usize::from(self.header.section_count) {
+      self.reader.seek(SeekFrom::Start(pos)).map_err(Error::Io)?;
+      let read = self.reader.read(&mut buf[..1]).map_err(Error::Io)?;
+      if read == 0 {
+        break;
+      }
+      if buf[0] == PADDING_CHAR {
+        pos += 1;
+        continue;
+      }
+
+      self.reader.seek(SeekFrom::Start(pos)).map_err(Error::Io)?;
+      let section = Section::from_reader(&mut self.reader, self.header.endianness)?;
+      let offset = pos + section.calc_size() as u64;
+      if offset + u64::from(section.size) > end {
+        return Err(Error::InvalidSectionSize(section.magic));
+      }
+      self.sections.push(SectionIndex {
+        magic: section.magic,
+        offset,
+        size: section.size,
+      });
+      pos = offset + u64::from(section.size);
+    }
+    Ok(())
+  }
+
+  fn index_lbl1(&mut self) -> Result<()> {
+    let lbl1 = match self.section(*b"LBL1") {
+      Some(s) => *s,
+      None => return Ok(()),
+    };
+    let endianness = self.header.endianness;
+
+    self.reader.seek(SeekFrom::Start(lbl1.offset)).map_err(Error::Io)?;
+    let group_count = endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+    if u64::from(group_count) * 8 + 4 > u64::from(lbl1.size) {
+      return Err(Error::InvalidSectionSize(lbl1.magic));
+    }
+    self.groups = (0..group_count)
+      .map(|_| Ok(Group {
+        label_count: endianness.read_u32(&mut self.reader).map_err(Error::Io)?,
+        offset: endianness.read_u32(&mut self.reader).map_err(Error::Io)?,
+      }))
+      .collect::<Result<_>>()?;
+    self.lbl1_offset = lbl1.offset;
+
+    Ok(())
+  }
+
+  fn index_txt2(&mut self) -> Result<()> {
+    let txt2 = match self.section(*b"TXT2") {
+      Some(s) => *s,
+      None => return Ok(()),
+    };
+    let endianness = self.header.endianness;
+    let invalid = || Error::InvalidSectionSize(txt2.magic);
+
+    self.reader.seek(SeekFrom::Start(txt2.offset)).map_err(Error::Io)?;
+    let count = endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+    if u64::from(count) * 4 + 4 > u64::from(txt2.size) {
+      return Err(invalid());
+    }
+    let offsets = (0..count)
+      .map(|_| endianness.read_u32(&mut self.reader).map_err(Error::Io))
+      .collect::<Result<Vec<_>>>()?;
+
+    self.strings = Vec::with_capacity(offsets.len());
+    for (i, &start) in offsets.iter().enumerate() {
+      let end = offsets.get(i + 1).copied().unwrap_or(txt2.size);
+      if start > end || end > txt2.size {
+        return Err(invalid());
+      }
+      self.strings.push((txt2.offset + u64::from(start), txt2.offset + u64::from(end)));
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(feature = "mmap")]
+impl LazyMsbt<std::io::Cursor<memmap2::Mmap>> {
+  /// Opens an MSBT file through a memory map, so only the parts that are accessed are loaded.
+  ///
+  /// # Safety
+  ///
+  /// The file must not be modified, by this or any other process, while it is mapped.
+  pub unsafe fn open_mmap<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+    let file = std::fs::File::open(path).map_err(Error::Io)?;
+    let map = memmap2::Mmap::map(&file).map_err(Error::Io)?;
+    LazyMsbt::new(std::io::Cursor::new(map))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::sample;
+
+  use std::io::Cursor;
+
+  #[test]
+  fn reads_embedded_files() {
+    let mut bytes = vec![0xff; 5];
+    bytes.extend(sample());
+    bytes.extend(b"MsgStdBn, and other files");
+
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(5);
+    let mut msbt = LazyMsbt::new(cursor).unwrap();
+    assert_eq!(msbt.sections().iter().map(|s| s.magic).collect::<Vec<_>>(), vec![*b"LBL1", *b"TXT2"]);
+    assert_eq!(msbt.get("farewell").unwrap().as_deref(), Some("Bye"));
+    assert_eq!(msbt.get("missing").unwrap(), None);
+  }
+
+  #[test]
+  fn rejects_strings_with_partial_code_units() {
+    let mut msbt = crate::Msbt::from_reader(Cursor::new(sample())).unwrap();
+    msbt.set_raw_string(1, vec![66, 0, 121]).unwrap();
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+
+    let mut msbt = LazyMsbt::new(Cursor::new(bytes)).unwrap();
+    assert!(matches!(msbt.string(1), Err(Error::UnexpectedEndOfMessage(3))));
+    assert_eq!(msbt.string(0).unwrap(), "Hello");
+  }
+
+  #[test]
+  fn rejects_sections_past_the_file_size() {
+    let mut bytes = sample();
+    let txt2 = bytes.windows(4).position(|w| w == b"TXT2").unwrap() as u32;
+    bytes[0x12..0x16].copy_from_slice(&txt2.to_le_bytes());
+
+    match LazyMsbt::new(Cursor::new(bytes)) {
+      Err(Error::InvalidSectionSize(magic)) => assert_eq!(&magic, b"TXT2"),
+      other => panic!("expected an invalid section size, got {:?}", other.map(|m| m.sections().to_vec())),
+    }
+  }
+}
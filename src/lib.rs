@@ -1,23 +1,35 @@
 use std::{
-  boxed::Box,
-  collections::BTreeMap,
+  collections::{BTreeMap, HashSet},
   io::{Read, Seek, SeekFrom, Write},
-  pin::Pin,
-  ptr::NonNull,
 };
 
 use byteordered::{Endianness, Endian};
 
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
 mod counter;
+#[cfg(test)]
+mod test_util;
 mod traits;
+pub mod borrowed;
 pub mod builder;
 pub mod error;
+pub mod lazy;
+pub mod markup;
+pub mod message;
+pub mod msbp;
+pub mod options;
+pub mod schema;
 pub mod section;
+pub mod stream;
 pub mod updater;
+pub mod validate;
 
 use self::{
   counter::Counter,
-  error::{Error, Result},
+  error::{Error, ErrorContext, ErrorItem, Result},
+  options::{ReadOptions, ReadWarning},
   section::{
     *,
     lbl1::{Group, Label},
@@ -34,7 +46,9 @@ const HEADER_MAGIC: [u8; 8] = *b"MsgStdBn";
 const PADDING_CHAR: u8 = 0xAB;
 const PADDING_LENGTH: usize = 16;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(rename_all = "snake_case"))]
 pub enum SectionTag {
   Lbl1,
   Nli1,
@@ -42,24 +56,74 @@ pub enum SectionTag {
   Atr1,
   Tsy1,
   Txt2,
+  /// An unknown section, by its index in [`Msbt::unknown_sections`].
+  Unknown(usize),
 }
 
-#[derive(Debug)]
+/// An MSBT file.
+///
+/// With the `serde_support` feature, a file can be saved to and loaded from any serde format.
+/// Everything needed to write the file back is kept, with strings, records and unknown sections as
+/// raw bytes, while sizes, counts, offsets and label hash groups are left out and recalculated
+/// when loading:
+///
+/// ```json
+/// {
+///   "header": {
+///     "magic": [77, 115, 103, 83, 116, 100, 66, 110],
+///     "endianness": "little",
+///     "unknown_1": 0,
+///     "encoding": "utf16",
+///     "version": 3,
+///     "unknown_3": 0,
+///     "padding": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+///   },
+///   "section_order": ["lbl1", "txt2", { "unknown": 0 }],
+///   "lbl1": {
+///     "section": { "magic": [76, 66, 76, 49], "padding": [0, 0, 0, 0, 0, 0, 0, 0] },
+///     "group_count": 101,
+///     "labels": [{ "name": "greeting", "index": 0 }]
+///   },
+///   "txt2": {
+///     "section": { "magic": [84, 88, 84, 50], "padding": [0, 0, 0, 0, 0, 0, 0, 0] },
+///     "raw_strings": [[72, 0, 105, 0, 0, 0]]
+///   },
+///   "unknown_sections": [
+///     { "section": { "magic": [90, 90, 90, 49], "padding": [0, 0, 0, 0, 0, 0, 0, 0] }, "bytes": [1, 2] }
+///   ]
+/// }
+/// ```
+///
+/// Missing sections may be left out or set to `null`. NLI1's `global_ids` map TXT2 indices to
+/// ids, ATO1 has `entries`, ATR1 has `entry_count`, `entry_size`, `records` (the bytes of every
+/// record, one after another) and `string_table`, and TSY1 has `styles`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(try_from = "MsbtData"))]
 pub struct Msbt {
   pub(crate) header: Header,
   pub(crate) section_order: Vec<SectionTag>,
-  // pinned because child labels have a reference to lbl1
-  pub(crate) lbl1: Option<Pin<Box<Lbl1>>>,
+  pub(crate) lbl1: Option<Lbl1>,
   pub(crate) nli1: Option<Nli1>,
   pub(crate) ato1: Option<Ato1>,
   pub(crate) atr1: Option<Atr1>,
   pub(crate) tsy1: Option<Tsy1>,
   pub(crate) txt2: Option<Txt2>,
+  pub(crate) unknown_sections: Vec<UnknownSection>,
 }
 
 impl Msbt {
-  pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Pin<Box<Self>>> {
-    MsbtReader::new(reader).map(MsbtReader::into_msbt)
+  pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+    MsbtReader::new(reader, ReadOptions::default()).map(MsbtReader::into_msbt)
+  }
+
+  /// Reads a file with the given options, returning any problems that were recovered from.
+  ///
+  /// Warnings are only produced when [`ReadOptions::strict`] is `false`.
+  pub fn from_reader_with_options<R: Read + Seek>(reader: R, options: ReadOptions) -> Result<(Self, Vec<ReadWarning>)> {
+    let mut reader = MsbtReader::new(reader, options)?;
+    let warnings = std::mem::take(&mut reader.warnings);
+    Ok((reader.into_msbt(), warnings))
   }
 
   pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
@@ -73,6 +137,7 @@ impl Msbt {
         SectionTag::Atr1 => writer.write_atr1()?,
         SectionTag::Tsy1 => writer.write_tsy1()?,
         SectionTag::Txt2 => writer.write_txt2()?,
+        SectionTag::Unknown(i) => writer.write_unknown(i)?,
       }
     }
     Ok(())
@@ -86,52 +151,198 @@ impl Msbt {
     &self.section_order
   }
 
-  pub fn lbl1(&self) -> Option<&Pin<Box<Lbl1>>> {
+  pub fn lbl1(&self) -> Option<&Lbl1> {
     self.lbl1.as_ref()
   }
 
-  pub fn lbl1_mut(&mut self) -> Option<Updater<Pin<Box<Lbl1>>>> {
-    self.lbl1.as_mut().map(Updater::new)
+  pub fn lbl1_mut(&mut self) -> Option<Updater<'_, Lbl1>> {
+    Updater::new(self, |m| m.lbl1.as_ref(), |m| m.lbl1.as_mut())
   }
 
   pub fn nli1(&self) -> Option<&Nli1> {
     self.nli1.as_ref()
   }
 
-  pub fn nli1_mut(&mut self) -> Option<&mut Nli1> {
-    self.nli1.as_mut()
+  pub fn nli1_mut(&mut self) -> Option<Updater<'_, Nli1>> {
+    Updater::new(self, |m| m.nli1.as_ref(), |m| m.nli1.as_mut())
   }
 
   pub fn ato1(&self) -> Option<&Ato1> {
     self.ato1.as_ref()
   }
 
-  pub fn ato1_mut(&mut self) -> Option<&mut Ato1> {
-    self.ato1.as_mut()
+  pub fn ato1_mut(&mut self) -> Option<Updater<'_, Ato1>> {
+    Updater::new(self, |m| m.ato1.as_ref(), |m| m.ato1.as_mut())
   }
 
   pub fn atr1(&self) -> Option<&Atr1> {
     self.atr1.as_ref()
   }
 
-  pub fn atr1_mut(&mut self) -> Option<&mut Atr1> {
-    self.atr1.as_mut()
+  pub fn atr1_mut(&mut self) -> Option<Updater<'_, Atr1>> {
+    Updater::new(self, |m| m.atr1.as_ref(), |m| m.atr1.as_mut())
   }
 
   pub fn tsy1(&self) -> Option<&Tsy1> {
     self.tsy1.as_ref()
   }
 
-  pub fn tsy1_mut(&mut self) -> Option<&mut Tsy1> {
-    self.tsy1.as_mut()
+  pub fn tsy1_mut(&mut self) -> Option<Updater<'_, Tsy1>> {
+    Updater::new(self, |m| m.tsy1.as_ref(), |m| m.tsy1.as_mut())
   }
 
   pub fn txt2(&self) -> Option<&Txt2> {
     self.txt2.as_ref()
   }
 
-  pub fn txt2_mut(&mut self) -> Option<Updater<Txt2>> {
-    self.txt2.as_mut().map(Updater::new)
+  pub fn txt2_mut(&mut self) -> Option<Updater<'_, Txt2>> {
+    Updater::new(self, |m| m.txt2.as_ref(), |m| m.txt2.as_mut())
+  }
+
+  /// Sections with magic numbers this crate does not recognise, in the order they were read.
+  pub fn unknown_sections(&self) -> &[UnknownSection] {
+    &self.unknown_sections
+  }
+
+  pub fn unknown_sections_mut(&mut self) -> Updater<'_, [UnknownSection]> {
+    Updater::new(self, |m| Some(&m.unknown_sections[..]), |m| Some(&mut m.unknown_sections[..]))
+      .expect("unknown sections are always present")
+  }
+
+  /// Adds a message with the label `name`, encoding `value` in the file's encoding and appending
+  /// a null terminator.
+  ///
+  /// See [`insert_message_raw`](Msbt::insert_message_raw).
+  pub fn insert_message<S: Into<String>>(&mut self, name: S, value: &str) -> Result<()> {
+    let raw = message::encode_message(value, self.header.encoding, self.header.endianness);
+    self.insert_message_raw(name, raw)
+  }
+
+  /// Adds a message with the label `name` and the raw string `value` at the end of TXT2.
+  ///
+  /// ATR1 and TSY1, if present, are extended to the new string count with zeroed records and
+  /// style 0, and string offsets in ATR1 records are moved along with its string table, which
+  /// follows the records. To add many messages, use [`insert_messages_raw`](Msbt::insert_messages_raw), which
+  /// only updates the file once.
+  pub fn insert_message_raw<S: Into<String>, V: Into<Vec<u8>>>(&mut self, name: S, value: V) -> Result<()> {
+    self.insert_messages_raw(std::iter::once((name, value)))
+  }
+
+  /// Adds messages from labels and text, encoding each in the file's encoding with a null
+  /// terminator.
+  ///
+  /// See [`insert_messages_raw`](Msbt::insert_messages_raw).
+  pub fn insert_messages<'a, I, S>(&mut self, messages: I) -> Result<()>
+    where I: IntoIterator<Item = (S, &'a str)>,
+          S: Into<String>,
+  {
+    let (encoding, endianness) = (self.header.encoding, self.header.endianness);
+    self.insert_messages_raw(messages
+      .into_iter()
+      .map(|(name, value)| (name, message::encode_message(value, encoding, endianness))))
+  }
+
+  /// Adds messages from labels and raw strings at the end of TXT2, in order.
+  ///
+  /// Every label is checked before anything is added, so nothing is added if one fails.
+  pub fn insert_messages_raw<I, S, V>(&mut self, messages: I) -> Result<()>
+    where I: IntoIterator<Item = (S, V)>,
+          S: Into<String>,
+          V: Into<Vec<u8>>,
+  {
+    let group_count = self.lbl1.as_ref().ok_or(Error::MissingSection(*b"LBL1"))?.group_count;
+    if group_count == 0 {
+      return Err(Error::InvalidGroupCount(group_count));
+    }
+
+    let mut names = HashSet::new();
+    let mut messages: Vec<(String, Vec<u8>)> = messages.into_iter()
+      .map(|(name, value)| (name.into(), value.into()))
+      .collect();
+    for (name, _) in &messages {
+      if name.len() > u8::MAX as usize {
+        return Err(Error::LabelTooLong(name.clone()));
+      }
+      if self.contains_label(name) || !names.insert(name.as_str()) {
+        return Err(Error::DuplicateLabel(name.clone()));
+      }
+    }
+
+    let txt2 = self.txt2_or_insert();
+    let first = txt2.raw_strings.len() as u32;
+    let string_count = first as usize + messages.len();
+    txt2.raw_strings.reserve(messages.len());
+    for (_, value) in &mut messages {
+      txt2.raw_strings.push(std::mem::take(value));
+    }
+
+    if let Some(atr1) = self.atr1.as_mut() {
+      atr1.extend_records(string_count, &self.header);
+    }
+    if let Some(tsy1) = self.tsy1.as_mut() {
+      if tsy1.styles.len() < string_count {
+        tsy1.styles.resize(string_count, 0);
+      }
+    }
+
+    if let Some(lbl1) = self.lbl1.as_mut() {
+      lbl1.labels.extend(messages.into_iter().zip(first..).map(|((name, _), index)| Label {
+        checksum: Label::hash(&name, group_count),
+        name,
+        index,
+      }));
+    }
+    self.update();
+    Ok(())
+  }
+
+  /// Removes the label `name` and, unless another label shares it, its message.
+  ///
+  /// Removing a message removes its TXT2 string, ATR1 record, TSY1 entry and NLI1 id, and shifts
+  /// the indices of later messages down. ATR1 strings that only the removed record refers to are
+  /// removed too.
+  pub fn remove_message(&mut self, name: &str) -> Result<()> {
+    let lbl1 = match self.lbl1.as_mut() {
+      Some(lbl1) => lbl1,
+      None => return Err(Error::NoSuchLabel(name.to_string())),
+    };
+    let position = lbl1.position(name).ok_or_else(|| Error::NoSuchLabel(name.to_string()))?;
+    let index = lbl1.labels.remove(position).index;
+
+    if lbl1.labels.iter().any(|l| l.index == index) {
+      self.update();
+      return Ok(());
+    }
+    for label in &mut lbl1.labels {
+      if label.index > index {
+        label.index -= 1;
+      }
+    }
+
+    let i = index as usize;
+    if let Some(txt2) = self.txt2.as_mut() {
+      if i < txt2.raw_strings.len() {
+        txt2.raw_strings.remove(i);
+      }
+    }
+    if let Some(atr1) = self.atr1.as_mut() {
+      atr1.remove_record(i, &self.header);
+    }
+    if let Some(tsy1) = self.tsy1.as_mut() {
+      if i < tsy1.styles.len() {
+        tsy1.styles.remove(i);
+      }
+    }
+    if let Some(nli1) = self.nli1.as_mut() {
+      nli1.global_ids = std::mem::take(&mut nli1.global_ids)
+        .into_iter()
+        .filter(|&(key, _)| key != index)
+        .map(|(key, id)| (if key > index { key - 1 } else { key }, id))
+        .collect();
+    }
+
+    self.update();
+    Ok(())
   }
 
   fn plus_padding(size: usize) -> usize {
@@ -144,22 +355,126 @@ impl Msbt {
   }
 }
 
+#[cfg(feature = "serde_support")]
+#[derive(Deserialize)]
+struct MsbtData {
+  header: Header,
+  section_order: Vec<SectionTag>,
+  lbl1: Option<Lbl1>,
+  nli1: Option<Nli1>,
+  ato1: Option<Ato1>,
+  atr1: Option<Atr1>,
+  tsy1: Option<Tsy1>,
+  txt2: Option<Txt2>,
+  #[serde(default)]
+  unknown_sections: Vec<UnknownSection>,
+}
+
+#[cfg(feature = "serde_support")]
+impl std::convert::TryFrom<MsbtData> for Msbt {
+  type Error = Error;
+
+  fn try_from(data: MsbtData) -> Result<Self> {
+    use crate::validate::Diagnostic;
+
+    let mut msbt = Msbt {
+      header: data.header,
+      section_order: data.section_order,
+      lbl1: data.lbl1,
+      nli1: data.nli1,
+      ato1: data.ato1,
+      atr1: data.atr1,
+      tsy1: data.tsy1,
+      txt2: data.txt2,
+      unknown_sections: data.unknown_sections,
+    };
+
+    match msbt.section_order_diagnostics().into_iter().next() {
+      Some(Diagnostic::MissingSection(magic)) => return Err(Error::MissingSection(magic)),
+      Some(Diagnostic::DuplicateSection(magic)) => return Err(Error::DuplicateSection(magic)),
+      Some(Diagnostic::UnorderedSection(magic)) => return Err(Error::UnorderedSection(magic)),
+      Some(Diagnostic::NoSuchUnknownSection(i)) => return Err(Error::NoSuchEntry(i)),
+      _ => {},
+    }
+    if let Some(atr1) = msbt.atr1.as_ref() {
+      if atr1.records.len() as u64 != atr1.entry_count as u64 * atr1.entry_size as u64 {
+        return Err(Error::InvalidRecordSize(atr1.records.len()));
+      }
+    }
+    // hash groups are not stored, so labels are sorted back into them
+    if let Some(lbl1) = msbt.lbl1.as_mut() {
+      if lbl1.group_count > 0 || !lbl1.labels.is_empty() {
+        lbl1.rehash(lbl1.group_count)?;
+      }
+    }
+
+    msbt.update();
+    Ok(msbt)
+  }
+}
+
+#[cfg(feature = "serde_support")]
+mod serde_endianness {
+  use byteordered::Endianness;
+  use serde::{
+    Deserialize,
+    Deserializer,
+    Serializer,
+    de::Error,
+  };
+
+  pub fn serialize<S: Serializer>(endianness: &Endianness, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(match *endianness {
+      Endianness::Big => "big",
+      Endianness::Little => "little",
+    })
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Endianness, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+      "big" => Ok(Endianness::Big),
+      "little" => Ok(Endianness::Little),
+      other => Err(D::Error::unknown_variant(other, &["big", "little"])),
+    }
+  }
+}
+
 impl CalculatesSize for Msbt {
-  // can't detect that Lbl1 is a Pin and has to be called in a redundant closure
-  #[allow(clippy::redundant_closure)]
   fn calc_size(&self) -> usize {
     self.header.calc_file_size()
-      + Msbt::plus_padding(self.lbl1.as_ref().map(|x| x.calc_size()).unwrap_or(0))
+      + Msbt::plus_padding(self.lbl1.as_ref().map(CalculatesSize::calc_size).unwrap_or(0))
       + Msbt::plus_padding(self.nli1.as_ref().map(CalculatesSize::calc_size).unwrap_or(0))
       + Msbt::plus_padding(self.ato1.as_ref().map(CalculatesSize::calc_size).unwrap_or(0))
       + Msbt::plus_padding(self.atr1.as_ref().map(CalculatesSize::calc_size).unwrap_or(0))
       + Msbt::plus_padding(self.tsy1.as_ref().map(CalculatesSize::calc_size).unwrap_or(0))
       + Msbt::plus_padding(self.txt2.as_ref().map(CalculatesSize::calc_size).unwrap_or(0))
+      + self.unknown_sections.iter().map(|x| Msbt::plus_padding(x.calc_size())).sum::<usize>()
   }
 }
 
 impl Updates for Msbt {
   fn update(&mut self) {
+    if let Some(lbl1) = self.lbl1.as_mut() {
+      lbl1.update();
+    }
+    if let Some(nli1) = self.nli1.as_mut() {
+      nli1.update();
+    }
+    if let Some(ato1) = self.ato1.as_mut() {
+      ato1.update();
+    }
+    if let Some(atr1) = self.atr1.as_mut() {
+      atr1.update();
+    }
+    if let Some(tsy1) = self.tsy1.as_mut() {
+      tsy1.update();
+    }
+    if let Some(txt2) = self.txt2.as_mut() {
+      txt2.update();
+    }
+    for unknown in &mut self.unknown_sections {
+      unknown.update();
+    }
     self.header.file_size = self.calc_size() as u32;
     self.header.section_count = self.section_order.len() as u16;
   }
@@ -180,24 +495,9 @@ impl<'a, W: Write> MsbtWriter<'a, W> {
   }
 
   fn write_header(&mut self) -> Result<()> {
-    self.writer.write_all(&self.msbt.header.magic).map_err(Error::Io)?;
-    let endianness = match self.msbt.header.endianness {
-      Endianness::Big => [0xFE, 0xFF],
-      Endianness::Little => [0xFF, 0xFE],
-    };
-    self.writer.write_all(&endianness).map_err(Error::Io)?;
-    self.msbt.header.endianness.write_u16(&mut self.writer, self.msbt.header._unknown_1).map_err(Error::Io)?;
-    let encoding_byte = match self.msbt.header.encoding {
-      Encoding::Utf8 => 0x00,
-      Encoding::Utf16 => 0x01,
-    };
-    self.writer.write_all(&[encoding_byte, self.msbt.header._unknown_2]).map_err(Error::Io)?;
-    self.msbt.header.endianness.write_u16(&mut self.writer, self.msbt.header.section_count).map_err(Error::Io)?;
-    self.msbt.header.endianness.write_u16(&mut self.writer, self.msbt.header._unknown_3).map_err(Error::Io)?;
-    self.msbt.header.endianness.write_u32(&mut self.writer, self.msbt.calc_size() as u32).map_err(Error::Io)?;
     // FIXME: update this as changes are made
-    // self.msbt.header.endianness.write_u32(&mut self.writer, self.msbt.header.file_size).map_err(Error::Io)?;
-    self.writer.write_all(&self.msbt.header.padding).map_err(Error::Io)
+    // self.msbt.header.write_to(&mut self.writer, self.msbt.header.file_size)
+    self.msbt.header.write_to(&mut self.writer, self.msbt.calc_size() as u32)
   }
 
   fn write_section(&mut self, section: &Section) -> Result<()> {
@@ -221,6 +521,9 @@ impl<'a, W: Write> MsbtWriter<'a, W> {
       let mut sorted_labels = lbl1.labels.clone(); // FIXME: don't clone
       sorted_labels.sort_by_key(|l| l.checksum);
       for label in &sorted_labels {
+        if label.name.len() > u8::MAX as usize {
+          return Err(Error::LabelTooLong(label.name.clone()));
+        }
         self.writer.write_all(&[label.name.len() as u8]).map_err(Error::Io)?;
         self.writer.write_all(label.name.as_bytes()).map_err(Error::Io)?;
         self.msbt.header.endianness.write_u32(&mut self.writer, label.index).map_err(Error::Io)?;
@@ -267,7 +570,7 @@ impl<'a, W: Write> MsbtWriter<'a, W> {
 
       // write strings
       for s in &txt2.raw_strings {
-        self.writer.write_all(&s).map_err(Error::Io)?;
+        self.writer.write_all(s).map_err(Error::Io)?;
       }
 
       self.write_padding()?;
@@ -279,7 +582,9 @@ impl<'a, W: Write> MsbtWriter<'a, W> {
   pub fn write_ato1(&mut self) -> Result<()> {
     if let Some(ref ato1) = self.msbt.ato1 {
       self.write_section(&ato1.section)?;
-      self.writer.write_all(&ato1._unknown).map_err(Error::Io)?;
+      for &entry in &ato1.entries {
+        self.msbt.header.endianness.write_u32(&mut self.writer, entry).map_err(Error::Io)?;
+      }
 
       self.write_padding()?;
     }
@@ -290,36 +595,11 @@ impl<'a, W: Write> MsbtWriter<'a, W> {
   pub fn write_atr1(&mut self) -> Result<()> {
     if let Some(ref atr1) = self.msbt.atr1 {
       self.write_section(&atr1.section)?;
-      self.msbt.header.endianness.write_u32(&mut self.writer, atr1.string_count).map_err(Error::Io)?;
-      self.msbt.header.endianness.write_u32(&mut self.writer, atr1._unknown_1).map_err(Error::Io)?;
-
-      let raw_strings: Vec<Vec<u8>> = atr1.strings
-        .iter()
-        .map(|string| match self.msbt.header.encoding() {
-          Encoding::Utf16 => {
-            let mut buf = [0; 2];
-            string.encode_utf16()
-              .flat_map(|u| {
-                self.msbt.header.endianness.write_u16(&mut buf[..], u).expect("failed to write to array");
-                buf.to_vec()
-              })
-              .collect()
-          },
-          Encoding::Utf8 => string.as_bytes().to_vec(),
-        })
-        .collect();
+      self.msbt.header.endianness.write_u32(&mut self.writer, atr1.entry_count).map_err(Error::Io)?;
+      self.msbt.header.endianness.write_u32(&mut self.writer, atr1.entry_size).map_err(Error::Io)?;
 
-      let mut offset = std::mem::size_of_val(&atr1.string_count)
-        + std::mem::size_of_val(&atr1._unknown_1)
-        + std::mem::size_of::<u32>() * atr1.strings.len();
-      for raw_string in &raw_strings {
-        self.msbt.header.endianness.write_u32(&mut self.writer, offset as u32).map_err(Error::Io)?;
-        offset += raw_string.len();
-      }
-
-      for raw_string in raw_strings {
-        self.writer.write_all(&raw_string).map_err(Error::Io)?;
-      }
+      self.writer.write_all(&atr1.records).map_err(Error::Io)?;
+      self.writer.write_all(&atr1.string_table).map_err(Error::Io)?;
 
       self.write_padding()?;
     }
@@ -330,7 +610,20 @@ impl<'a, W: Write> MsbtWriter<'a, W> {
   pub fn write_tsy1(&mut self) -> Result<()> {
     if let Some(ref tsy1) = self.msbt.tsy1 {
       self.write_section(&tsy1.section)?;
-      self.writer.write_all(&tsy1._unknown).map_err(Error::Io)?;
+      for &style in &tsy1.styles {
+        self.msbt.header.endianness.write_u32(&mut self.writer, style).map_err(Error::Io)?;
+      }
+
+      self.write_padding()?;
+    }
+
+    Ok(())
+  }
+
+  pub fn write_unknown(&mut self, index: usize) -> Result<()> {
+    if let Some(unknown) = self.msbt.unknown_sections.get(index) {
+      self.write_section(&unknown.section)?;
+      self.writer.write_all(&unknown.bytes).map_err(Error::Io)?;
 
       self.write_padding()?;
     }
@@ -353,17 +646,32 @@ pub struct MsbtReader<R> {
   reader: R,
   section_order: Vec<SectionTag>,
   header: Header,
-  lbl1: Option<Pin<Box<Lbl1>>>,
+  lbl1: Option<Lbl1>,
   nli1: Option<Nli1>,
   ato1: Option<Ato1>,
   atr1: Option<Atr1>,
   tsy1: Option<Tsy1>,
   txt2: Option<Txt2>,
+  unknown_sections: Vec<UnknownSection>,
+  options: ReadOptions,
+  warnings: Vec<ReadWarning>,
+  end: u64,
+  // the item being parsed, for error context
+  item: Option<ErrorItem>,
 }
 
 impl<R: Read + Seek> MsbtReader<R> {
-  fn new(mut reader: R) -> Result<Self> {
-    let header = Header::from_reader(&mut reader)?;
+  fn new(mut reader: R, options: ReadOptions) -> Result<Self> {
+    let header = match Header::from_reader(&mut reader) {
+      Ok(header) => header,
+      Err(e) => return Err(e.with_context(ErrorContext {
+        offset: reader.stream_position().ok(),
+        ..Default::default()
+      })),
+    };
+    let pos = reader.stream_position().map_err(Error::Io)?;
+    let end = reader.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+    reader.seek(SeekFrom::Start(pos)).map_err(Error::Io)?;
 
     let mut msbt = MsbtReader {
       reader,
@@ -374,7 +682,12 @@ impl<R: Read + Seek> MsbtReader<R> {
       atr1: None,
       tsy1: None,
       txt2: None,
+      unknown_sections: Vec::new(),
       section_order: Vec::with_capacity(6),
+      options,
+      warnings: Vec::new(),
+      end,
+      item: None,
     };
 
     msbt.read_sections()?;
@@ -382,8 +695,8 @@ impl<R: Read + Seek> MsbtReader<R> {
     Ok(msbt)
   }
 
-  fn into_msbt(self) -> Pin<Box<Msbt>> {
-    let msbt = Msbt {
+  fn into_msbt(self) -> Msbt {
+    Msbt {
       header: self.header,
       section_order: self.section_order,
       lbl1: self.lbl1,
@@ -392,34 +705,8 @@ impl<R: Read + Seek> MsbtReader<R> {
       atr1: self.atr1,
       tsy1: self.tsy1,
       txt2: self.txt2,
-    };
-    let mut pinned_msbt = Box::pin(msbt);
-
-    let msbt_ref: &mut Msbt = unsafe {
-      let mut_ref: Pin<&mut Msbt> = Pin::as_mut(&mut pinned_msbt);
-      Pin::get_unchecked_mut(mut_ref)
-    };
-    let ptr = NonNull::new(msbt_ref as *mut Msbt).unwrap();
-    if let Some(lbl1) = msbt_ref.lbl1.as_mut() {
-      lbl1.msbt = ptr;
-    }
-    if let Some(mut nli1) = msbt_ref.nli1.as_mut() {
-      nli1.msbt = ptr;
+      unknown_sections: self.unknown_sections,
     }
-    if let Some(mut ato1) = msbt_ref.ato1.as_mut() {
-      ato1.msbt = ptr;
-    }
-    if let Some(mut atr1) = msbt_ref.atr1.as_mut() {
-      atr1.msbt = ptr;
-    }
-    if let Some(mut tsy1) = msbt_ref.tsy1.as_mut() {
-      tsy1.msbt = ptr;
-    }
-    if let Some(mut txt2) = msbt_ref.txt2.as_mut() {
-      txt2.msbt = ptr;
-    }
-
-    pinned_msbt
   }
 
   fn skip_padding(&mut self) -> Result<()> {
@@ -430,7 +717,7 @@ impl<R: Read + Seek> MsbtReader<R> {
         return Ok(());
       }
       if let Some(i) = buf[..read].iter().position(|&x| x != PADDING_CHAR) {
-        self.reader.seek(SeekFrom::Current(i as i64 - 16)).map_err(Error::Io)?;
+        self.reader.seek(SeekFrom::Current(i as i64 - read as i64)).map_err(Error::Io)?;
         return Ok(());
       }
     }
@@ -445,41 +732,100 @@ impl<R: Read + Seek> MsbtReader<R> {
         Err(e) => return Err(Error::Io(e)),
       }
 
-      self.reader.seek(SeekFrom::Current(-4)).map_err(Error::Io)?;
-
-      match &peek {
-        b"LBL1" => {
-          self.lbl1 = Some(self.read_lbl1()?);
-          self.section_order.push(SectionTag::Lbl1);
-        },
-        b"ATR1" => {
-          self.atr1 = Some(self.read_atr1()?);
-          self.section_order.push(SectionTag::Atr1);
-        },
-        b"ATO1" => {
-          self.ato1 = Some(self.read_ato1()?);
-          self.section_order.push(SectionTag::Ato1);
-        },
-        b"TSY1" => {
-          self.tsy1 = Some(self.read_tsy1()?);
-          self.section_order.push(SectionTag::Tsy1);
-        },
-        b"TXT2" => {
-          self.txt2 = Some(self.read_txt2()?);
-          self.section_order.push(SectionTag::Txt2);
-        },
-        b"NLI1" => {
-          self.nli1 = Some(self.read_nli1()?);
-          self.section_order.push(SectionTag::Nli1);
-        },
-        _ => return Err(Error::InvalidSection(peek)),
+      let start = self.reader.seek(SeekFrom::Current(-4)).map_err(Error::Io)?;
+      let available = self.end - start;
+
+      if !self.options.strict && (available < 16 || !peek.iter().all(u8::is_ascii_alphanumeric)) {
+        self.warnings.push(ReadWarning::TrailingData {
+          offset: start,
+          len: available,
+        });
+        self.reader.seek(SeekFrom::End(0)).map_err(Error::Io)?;
+        return Ok(());
       }
 
+      // peek at the section header to know where the section ends, whatever the parser consumes
+      let section = Section::from_reader(&mut self.reader, self.header.endianness)
+        .map_err(|e| e.with_context(ErrorContext {
+          offset: Some(start),
+          section: Some(peek),
+          item: Some(ErrorItem::SectionHeader(self.section_order.len() as u32)),
+        }))?;
+      let body_available = (available - section.calc_size() as u64).min(u64::from(u32::MAX)) as u32;
+      if !self.options.strict && section.size > body_available {
+        self.warnings.push(ReadWarning::TruncatedSection {
+          magic: section.magic,
+          offset: start,
+          declared: section.size,
+          available: body_available,
+        });
+      }
+      let section_end = start + section.calc_size() as u64 + u64::from(section.size.min(body_available));
+      self.reader.seek(SeekFrom::Start(start)).map_err(Error::Io)?;
+
+      self.item = None;
+      if let Err(e) = self.read_known_section(&section) {
+        let e = e.with_context(ErrorContext {
+          offset: self.reader.stream_position().ok(),
+          section: Some(peek),
+          item: self.item.take(),
+        });
+        if self.options.strict {
+          return Err(e);
+        }
+        self.warnings.push(ReadWarning::UnparsedSection {
+          magic: peek,
+          offset: start,
+          reason: e.to_string(),
+        });
+        self.reader.seek(SeekFrom::Start(start)).map_err(Error::Io)?;
+        let unknown = self.read_unknown()?;
+        self.section_order.push(SectionTag::Unknown(self.unknown_sections.len()));
+        self.unknown_sections.push(unknown);
+      }
+
+      self.reader.seek(SeekFrom::Start(section_end)).map_err(Error::Io)?;
       self.skip_padding()?;
     }
   }
 
-  pub fn read_lbl1(&mut self) -> Result<Pin<Box<Lbl1>>> {
+  fn read_known_section(&mut self, section: &Section) -> Result<()> {
+    // ATO1 and TSY1 sizes that are not a whole number of entries are kept as unknown sections
+    match &section.magic {
+      b"LBL1" => {
+        self.lbl1 = Some(self.read_lbl1()?);
+        self.section_order.push(SectionTag::Lbl1);
+      },
+      b"ATR1" => {
+        self.atr1 = Some(self.read_atr1()?);
+        self.section_order.push(SectionTag::Atr1);
+      },
+      b"ATO1" if section.size % 4 == 0 => {
+        self.ato1 = Some(self.read_ato1()?);
+        self.section_order.push(SectionTag::Ato1);
+      },
+      b"TSY1" if section.size % 4 == 0 => {
+        self.tsy1 = Some(self.read_tsy1()?);
+        self.section_order.push(SectionTag::Tsy1);
+      },
+      b"TXT2" => {
+        self.txt2 = Some(self.read_txt2()?);
+        self.section_order.push(SectionTag::Txt2);
+      },
+      b"NLI1" => {
+        self.nli1 = Some(self.read_nli1()?);
+        self.section_order.push(SectionTag::Nli1);
+      },
+      _ => {
+        let unknown = self.read_unknown()?;
+        self.section_order.push(SectionTag::Unknown(self.unknown_sections.len()));
+        self.unknown_sections.push(unknown);
+      },
+    }
+    Ok(())
+  }
+
+  pub fn read_lbl1(&mut self) -> Result<Lbl1> {
     let section = self.read_section()?;
 
     if &section.magic != b"LBL1" {
@@ -487,29 +833,50 @@ impl<R: Read + Seek> MsbtReader<R> {
     }
 
     let group_count = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+    if u64::from(group_count) * 8 + 4 > u64::from(section.size) {
+      return Err(Error::InvalidSectionSize(section.magic));
+    }
 
     let mut groups = Vec::with_capacity(group_count as usize);
 
-    for _ in 0..group_count {
+    for i in 0..group_count {
+      self.item = Some(ErrorItem::Group(i));
       groups.push(self.read_group()?);
     }
 
-    let mut labels = Vec::with_capacity(groups.iter().map(|x| x.label_count as usize).sum());
+    // each label takes at least five bytes, which bounds the count a damaged table can claim
+    let label_count = groups.iter().map(|x| x.label_count as usize).sum::<usize>();
+    let mut labels = Vec::with_capacity(label_count.min(section.size as usize / 5));
 
     let mut buf = [0; 1];
     for (i, group) in groups.iter().enumerate() {
-      for _ in 0..group.label_count {
+      for j in 0..group.label_count {
+        self.item = Some(ErrorItem::Label {
+          group: i as u32,
+          index: j,
+        });
         self.reader.read_exact(&mut buf).map_err(Error::Io)?;
         let str_len = buf[0] as usize;
 
+        let offset = self.reader.stream_position().map_err(Error::Io)?;
         let mut str_buf = vec![0; str_len];
         self.reader.read_exact(&mut str_buf).map_err(Error::Io)?;
-        let name = String::from_utf8(str_buf).map_err(Error::InvalidUtf8)?;
+        let name = match String::from_utf8(str_buf) {
+          Ok(name) => name,
+          Err(e) if !self.options.strict => {
+            let name = String::from_utf8_lossy(e.as_bytes()).into_owned();
+            self.warnings.push(ReadWarning::LossyLabelName {
+              offset,
+              name: name.clone(),
+            });
+            name
+          },
+          Err(e) => return Err(Error::InvalidUtf8(e)),
+        };
         let index = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
         let checksum = i as u32;
 
         labels.push(Label {
-          lbl1: NonNull::dangling(),
           name,
           index,
           checksum,
@@ -517,124 +884,128 @@ impl<R: Read + Seek> MsbtReader<R> {
       }
     }
 
-    let lbl1 = Lbl1 {
-      msbt: NonNull::dangling(),
-      section,
-      group_count,
-      groups,
-      labels,
-    };
-    let mut pinned_lbl1 = Box::pin(lbl1);
-
-    let lbl1_ref: &mut Lbl1 = unsafe {
-      let mut_ref: Pin<&mut Lbl1> = Pin::as_mut(&mut pinned_lbl1);
-      Pin::get_unchecked_mut(mut_ref)
-    };
-    let ptr = NonNull::new(lbl1_ref as *mut Lbl1).unwrap();
-    for mut label in &mut lbl1_ref.labels {
-      label.lbl1 = ptr;
-    }
-
-    Ok(pinned_lbl1)
+    Ok(Lbl1::new(section, group_count, groups, labels))
   }
 
   pub fn read_atr1(&mut self) -> Result<Atr1> {
     let section = self.read_section()?;
 
-    let string_count = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
-    let _unknown_1 = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+    let entry_count = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+    let entry_size = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
 
-    let strings = if section.size == 8 {
-      Vec::new()
-    } else {
-      let mut offsets = Vec::with_capacity(string_count as usize);
-      for _ in 0..string_count {
-        offsets.push(self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?);
-      }
+    let records_size = entry_count as u64 * entry_size as u64;
+    let string_table_size = (section.size as u64)
+      .checked_sub(8 + records_size)
+      .ok_or(Error::InvalidSectionSize(section.magic))?;
 
-      let mut strings = Vec::with_capacity(string_count as usize);
-      for i in 0..string_count {
-        let next_str_end = if i == string_count - 1 {
-          section.size
-        } else {
-          offsets[i as usize + 1]
-        };
-        let str_len = next_str_end - offsets[i as usize];
-        let mut str_buf = vec![0; str_len as usize];
-        self.reader.read_exact(&mut str_buf).map_err(Error::Io)?;
-        let string = match self.header.encoding {
-          Encoding::Utf16 => {
-            let u16s: Vec<u16> = str_buf.chunks(2)
-              .map(|bs| self.header.endianness.read_u16(bs).expect("reading from chunk failed"))
-              .collect();
-            String::from_utf16(&u16s).map_err(Error::InvalidUtf16)?
-          },
-          Encoding::Utf8 => String::from_utf8(str_buf).map_err(Error::InvalidUtf8)?,
-        };
-        strings.push(string);
-      }
+    // the section size bounds the records, so they are read in one go
+    let mut records = vec![0; records_size as usize];
+    self.reader.read_exact(&mut records).map_err(Error::Io)?;
 
-      strings
-    };
+    let mut string_table = vec![0; string_table_size as usize];
+    self.reader.read_exact(&mut string_table).map_err(Error::Io)?;
 
     Ok(Atr1 {
-      msbt: NonNull::dangling(),
       section,
-      string_count,
-      _unknown_1,
-      strings,
+      entry_count,
+      entry_size,
+      records,
+      string_table,
     })
   }
 
   pub fn read_ato1(&mut self) -> Result<Ato1> {
     let section = self.read_section()?;
-    let mut unknown = vec![0; section.size as usize];
-    self.reader.read_exact(&mut unknown).map_err(Error::Io)?;
+    if section.size % 4 != 0 {
+      return Err(Error::InvalidSectionSize(section.magic));
+    }
+
+    let mut entries = Vec::with_capacity(section.size as usize / 4);
+    for i in 0..section.size / 4 {
+      self.item = Some(ErrorItem::Entry(i));
+      entries.push(self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?);
+    }
 
     Ok(Ato1 {
-      msbt: NonNull::dangling(),
       section,
-      _unknown: unknown,
+      entries,
     })
   }
 
   pub fn read_tsy1(&mut self) -> Result<Tsy1> {
     let section = self.read_section()?;
-    let mut unknown = vec![0; section.size as usize];
-    self.reader.read_exact(&mut unknown).map_err(Error::Io)?;
+    if section.size % 4 != 0 {
+      return Err(Error::InvalidSectionSize(section.magic));
+    }
+
+    let mut styles = Vec::with_capacity(section.size as usize / 4);
+    for i in 0..section.size / 4 {
+      self.item = Some(ErrorItem::Entry(i));
+      styles.push(self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?);
+    }
 
     Ok(Tsy1 {
-      msbt: NonNull::dangling(),
       section,
-      _unknown: unknown,
+      styles,
+    })
+  }
+
+  pub fn read_unknown(&mut self) -> Result<UnknownSection> {
+    let section = self.read_section()?;
+    let mut bytes = vec![0; section.size as usize];
+    self.reader.read_exact(&mut bytes).map_err(Error::Io)?;
+
+    Ok(UnknownSection {
+      section,
+      bytes,
     })
   }
 
   pub fn read_txt2(&mut self) -> Result<Txt2> {
     let section = self.read_section()?;
+    let body_start = self.reader.stream_position().map_err(Error::Io)?;
     let string_count = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)? as usize;
+    if string_count as u64 * 4 + 4 > u64::from(section.size) {
+      return Err(Error::InvalidSectionSize(section.magic));
+    }
 
     let mut offsets = Vec::with_capacity(string_count);
     let mut raw_strings = Vec::with_capacity(string_count);
 
-    for _ in 0..string_count {
-      offsets.push(self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?);
+    let mut min_offset = (string_count as u32).saturating_mul(4).saturating_add(4);
+    for i in 0..string_count {
+      self.item = Some(ErrorItem::String(i as u32));
+      let offset = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
+      let clamped = offset.max(min_offset).min(section.size);
+      if clamped != offset {
+        if self.options.strict {
+          return Err(Error::InvalidStringOffset(offset));
+        }
+        self.warnings.push(ReadWarning::ClampedStringOffset {
+          index: i as u32,
+          offset,
+          clamped,
+        });
+      }
+      min_offset = clamped;
+      offsets.push(clamped);
     }
 
     for i in 0..string_count {
+      self.item = Some(ErrorItem::String(i as u32));
       let next_str_end = if i == string_count - 1 {
         section.size
       } else {
         offsets[i + 1]
       };
       let str_len = next_str_end - offsets[i];
+      self.reader.seek(SeekFrom::Start(body_start + u64::from(offsets[i]))).map_err(Error::Io)?;
       let mut str_buf = vec![0; str_len as usize];
       self.reader.read_exact(&mut str_buf).map_err(Error::Io)?;
       raw_strings.push(str_buf);
     }
 
     Ok(Txt2 {
-      msbt: NonNull::dangling(),
       section,
       string_count: string_count as u32,
       raw_strings,
@@ -650,7 +1021,8 @@ impl<R: Read + Seek> MsbtReader<R> {
     if section.size > 0 {
       id_count = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
 
-      for _ in 0..id_count {
+      for i in 0..id_count {
+        self.item = Some(ErrorItem::Entry(i));
         let val = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
         let key = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
         map.insert(key, val);
@@ -658,7 +1030,6 @@ impl<R: Read + Seek> MsbtReader<R> {
     }
 
     Ok(Nli1 {
-      msbt: NonNull::dangling(),
       section,
       id_count,
       global_ids: map,
@@ -676,42 +1047,64 @@ impl<R: Read + Seek> MsbtReader<R> {
   }
 
   pub fn read_section(&mut self) -> Result<Section> {
-    let mut magic = [0; 4];
-    let mut padding = [0; 8];
-
-    self.reader.read_exact(&mut magic).map_err(Error::Io)?;
-    let size = self.header.endianness.read_u32(&mut self.reader).map_err(Error::Io)?;
-    self.reader.read_exact(&mut padding).map_err(Error::Io)?;
-
-    Ok(Section {
-      magic,
-      size,
-      padding,
-    })
+    let mut section = Section::from_reader(&mut self.reader, self.header.endianness)?;
+    if !self.options.strict {
+      // lenient reading truncates sections to the data that is actually there
+      let pos = self.reader.stream_position().map_err(Error::Io)?;
+      let available = self.end.saturating_sub(pos).min(u64::from(u32::MAX)) as u32;
+      section.size = section.size.min(available);
+    }
+    Ok(section)
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Header {
   pub(crate) magic: [u8; 8],
+  #[cfg_attr(feature = "serde_support", serde(with = "serde_endianness"))]
   pub(crate) endianness: Endianness,
+  #[cfg_attr(feature = "serde_support", serde(rename = "unknown_1"))]
   pub(crate) _unknown_1: u16,
   pub(crate) encoding: Encoding,
-  pub(crate) _unknown_2: u8,
+  pub(crate) version: u8,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub(crate) section_count: u16,
+  #[cfg_attr(feature = "serde_support", serde(rename = "unknown_3"))]
   pub(crate) _unknown_3: u16,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub(crate) file_size: u32,
   pub(crate) padding: [u8; 10],
 }
 
 impl Header {
-  pub fn from_reader(mut reader: &mut dyn Read) -> Result<Self> {
+  /// Creates an MSBT header for a new file, with the lowest version that supports `encoding`.
+  pub(crate) fn new(endianness: Endianness, encoding: Encoding) -> Self {
+    Header {
+      magic: HEADER_MAGIC,
+      endianness,
+      _unknown_1: 0,
+      encoding,
+      // UTF-32 was introduced in version 4
+      version: if encoding == Encoding::Utf32 { 4 } else { 3 },
+      section_count: 0,
+      _unknown_3: 0,
+      file_size: 0,
+      padding: [0; 10],
+    }
+  }
+
+  pub fn from_reader(reader: &mut dyn Read) -> Result<Self> {
+    Header::from_reader_with_magic(reader, HEADER_MAGIC)
+  }
+
+  pub(crate) fn from_reader_with_magic(mut reader: &mut dyn Read, expected_magic: [u8; 8]) -> Result<Self> {
     let mut buf = [0u8; 10];
     reader.read_exact(&mut buf[..8]).map_err(Error::Io)?;
 
     let mut magic = [0u8; 8];
     magic.swap_with_slice(&mut buf[..8]);
-    if magic != HEADER_MAGIC {
+    if magic != expected_magic {
       return Err(Error::InvalidMagic);
     }
 
@@ -731,11 +1124,12 @@ impl Header {
     let encoding = match buf[0] {
       0x00 => Encoding::Utf8,
       0x01 => Encoding::Utf16,
+      0x02 => Encoding::Utf32,
       x => return Err(Error::InvalidEncoding(x)),
     };
 
     reader.read_exact(&mut buf[..1]).map_err(Error::Io)?;
-    let unknown_2 = buf[0];
+    let version = buf[0];
 
     let section_count = endianness.read_u16(&mut reader).map_err(Error::Io)?;
 
@@ -750,15 +1144,30 @@ impl Header {
       magic,
       endianness,
       encoding,
+      version,
       section_count,
       file_size,
       padding,
       _unknown_1: unknown_1,
-      _unknown_2: unknown_2,
       _unknown_3: unknown_3,
     })
   }
 
+  pub(crate) fn write_to<W: Write>(&self, mut writer: W, file_size: u32) -> Result<()> {
+    writer.write_all(&self.magic).map_err(Error::Io)?;
+    let endianness = match self.endianness {
+      Endianness::Big => [0xFE, 0xFF],
+      Endianness::Little => [0xFF, 0xFE],
+    };
+    writer.write_all(&endianness).map_err(Error::Io)?;
+    self.endianness.write_u16(&mut writer, self._unknown_1).map_err(Error::Io)?;
+    writer.write_all(&[self.encoding as u8, self.version]).map_err(Error::Io)?;
+    self.endianness.write_u16(&mut writer, self.section_count).map_err(Error::Io)?;
+    self.endianness.write_u16(&mut writer, self._unknown_3).map_err(Error::Io)?;
+    self.endianness.write_u32(&mut writer, file_size).map_err(Error::Io)?;
+    writer.write_all(&self.padding).map_err(Error::Io)
+  }
+
   pub fn magic(&self) -> [u8; 8] {
     self.magic
   }
@@ -775,8 +1184,9 @@ impl Header {
     self.encoding
   }
 
-  pub fn unknown_2(&self) -> u8 {
-    self._unknown_2
+  /// The format version. Version 4 files may use UTF-32.
+  pub fn version(&self) -> u8 {
+    self.version
   }
 
   pub fn section_count(&self) -> u16 {
@@ -800,7 +1210,7 @@ impl Header {
       + std::mem::size_of::<u16>() // endianness
       + std::mem::size_of_val(&self._unknown_1)
       + std::mem::size_of::<u8>() // encoding
-      + std::mem::size_of_val(&self._unknown_2)
+      + std::mem::size_of_val(&self.version)
       + std::mem::size_of_val(&self.section_count)
       + std::mem::size_of_val(&self._unknown_3)
       + std::mem::size_of_val(&self.file_size)
@@ -809,8 +1219,162 @@ impl Header {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(rename_all = "snake_case"))]
 pub enum Encoding {
   Utf8 = 0x00,
   Utf16 = 0x01,
+  Utf32 = 0x02,
 }
 
+impl Encoding {
+  /// The size in bytes of one code unit in this encoding.
+  pub fn unit_size(self) -> usize {
+    match self {
+      Encoding::Utf8 => 1,
+      Encoding::Utf16 => 2,
+      Encoding::Utf32 => 4,
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{builder::MsbtBuilder, test_util::sample, validate::Diagnostic};
+
+  use std::io::Cursor;
+
+  fn read_lenient(bytes: Vec<u8>) -> (Msbt, Vec<ReadWarning>) {
+    Msbt::from_reader_with_options(Cursor::new(bytes), ReadOptions { strict: false }).unwrap()
+  }
+
+  #[test]
+  fn warns_about_trailing_data() {
+    let mut bytes = sample();
+    let len = bytes.len() as u64;
+    bytes.extend(&[PADDING_CHAR; 3]);
+    bytes.extend(b"junk!");
+
+    let (msbt, warnings) = read_lenient(bytes);
+    assert_eq!(warnings, vec![ReadWarning::TrailingData { offset: len + 3, len: 5 }]);
+    assert_eq!(msbt.get("farewell").unwrap().as_deref(), Some("Bye"));
+  }
+
+  #[test]
+  fn warns_about_truncated_sections() {
+    let mut bytes = sample();
+    let txt2 = bytes.windows(4).position(|w| w == b"TXT2").unwrap();
+    bytes.truncate(txt2 + 16 + 12);
+
+    assert!(Msbt::from_reader(Cursor::new(bytes.clone())).is_err());
+    let (msbt, warnings) = read_lenient(bytes);
+    assert!(matches!(
+      warnings.first(),
+      Some(ReadWarning::TruncatedSection { magic, declared, available: 12, .. }) if magic == b"TXT2" && *declared > 12,
+    ));
+    assert!(msbt.lbl1().is_some());
+  }
+
+  #[test]
+  fn rejects_strings_with_partial_code_units() {
+    let mut msbt = Msbt::from_reader(Cursor::new(sample())).unwrap();
+    msbt.set_raw_string(0, vec![65, 0, 66]).unwrap();
+    let err = msbt.string(0).unwrap_err();
+    assert!(matches!(err.inner(), Error::UnexpectedEndOfMessage(3)));
+    assert!(msbt.strings().is_err());
+  }
+
+  #[test]
+  fn reads_written_files() {
+    let bytes = sample();
+    let msbt = Msbt::from_reader(Cursor::new(&bytes)).unwrap();
+    assert_eq!(msbt.get("greeting").unwrap().as_deref(), Some("Hello"));
+    assert_eq!(msbt.header().file_size() as usize, bytes.len());
+
+    let mut written = Vec::new();
+    msbt.write_to(&mut written).unwrap();
+    assert_eq!(written, bytes);
+  }
+
+  #[test]
+  fn validates_section_order() {
+    let mut msbt = Msbt::from_reader(Cursor::new(sample())).unwrap();
+    msbt.section_order = vec![SectionTag::Txt2, SectionTag::Txt2, SectionTag::Atr1, SectionTag::Unknown(0)];
+    let diagnostics = msbt.validate();
+    for diagnostic in &[
+      Diagnostic::DuplicateSection(*b"TXT2"),
+      Diagnostic::MissingSection(*b"ATR1"),
+      Diagnostic::NoSuchUnknownSection(0),
+      Diagnostic::UnorderedSection(*b"LBL1"),
+    ] {
+      assert!(diagnostics.contains(diagnostic), "{:?} not in {:?}", diagnostic, diagnostics);
+    }
+  }
+
+  #[cfg(feature = "serde_support")]
+  #[test]
+  fn round_trips_through_serde() {
+    let bytes = sample();
+    let msbt = Msbt::from_reader(Cursor::new(&bytes)).unwrap();
+    let msbt: Msbt = serde_json::from_str(&serde_json::to_string(&msbt).unwrap()).unwrap();
+
+    let mut written = Vec::new();
+    msbt.write_to(&mut written).unwrap();
+    assert_eq!(written, bytes);
+  }
+
+  #[cfg(feature = "serde_support")]
+  #[test]
+  fn rejects_invalid_section_orders() {
+    let msbt = Msbt::from_reader(Cursor::new(sample())).unwrap();
+    let cases = vec![
+      (serde_json::json!(["lbl1", "txt2", "txt2"]), Error::DuplicateSection(*b"TXT2")),
+      (serde_json::json!(["lbl1"]), Error::UnorderedSection(*b"TXT2")),
+      (serde_json::json!(["lbl1", "atr1", "txt2"]), Error::MissingSection(*b"ATR1")),
+      (serde_json::json!(["lbl1", "txt2", { "unknown": 0 }]), Error::NoSuchEntry(0)),
+    ];
+    for (order, expected) in cases {
+      let mut value = serde_json::to_value(&msbt).unwrap();
+      value["section_order"] = order;
+      let err = serde_json::from_value::<Msbt>(value).unwrap_err();
+      assert!(err.to_string().contains(&expected.to_string()), "{} is not {}", err, expected);
+    }
+  }
+
+  #[test]
+  fn keeps_sections_with_partial_entries() {
+    let msbt = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(1))
+      .message("a", "A")
+      .unknown_section(UnknownSection::new(*b"ATO1", vec![1, 2, 3, 4, 5, 6]))
+      .unknown_section(UnknownSection::new(*b"TSY1", vec![1, 2]))
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+
+    let msbt = Msbt::from_reader(Cursor::new(&bytes)).unwrap();
+    assert!(msbt.ato1().is_none() && msbt.tsy1().is_none());
+    assert_eq!(msbt.unknown_sections().iter().map(|s| s.bytes.len()).collect::<Vec<_>>(), vec![6, 2]);
+
+    let mut written = Vec::new();
+    msbt.write_to(&mut written).unwrap();
+    assert_eq!(written, bytes);
+  }
+
+  #[test]
+  fn reports_where_section_headers_are_cut() {
+    let bytes = sample();
+    let txt2 = bytes.windows(4).position(|w| w == b"TXT2").unwrap();
+    for (cut, section, index) in [(40, *b"LBL1", 0), (txt2 + 8, *b"TXT2", 1)] {
+      let err = Msbt::from_reader(Cursor::new(&bytes[..cut])).unwrap_err();
+      assert_eq!(err.context(), Some(&ErrorContext {
+        offset: Some(cut as u64 - 8),
+        section: Some(section),
+        item: Some(ErrorItem::SectionHeader(index)),
+      }));
+      assert!(matches!(err.inner(), Error::Io(_)));
+    }
+  }
+}
@@ -0,0 +1,434 @@
+//! A textual markup for messages containing control tags.
+//!
+//! Text is written as-is, except that `\` and `[` must be escaped as `\\` and `\[`. Control tags
+//! are written as `[group:type]`, with their parameters as hex bytes after a space if they have any
+//! (`[0:3 ff000000]`). Closing tags are written as `[/group:type]`. A null inside a string is
+//! written as `\0`, and a string without a null terminator ends with `\$`. The control characters
+//! U+0000, U+000E and U+000F cannot be written as text.
+//!
+//! When a [`TagSchema`] is given, tags it knows are written by name with named parameters instead
+//! (`[color r=255 g=0 b=0 a=255]`, `[/color]`). String parameters are quoted, and enum parameters
+//! are written as their item name. Tags whose parameters do not match the schema are still written
+//! in the numeric form, so the round trip is always exact.
+
+use crate::{
+  Encoding,
+  error::{Error, Result},
+  message::Segment,
+  schema::{ParamType, ParamValue, TagParams, TagSchema},
+};
+
+use byteordered::Endianness;
+
+use std::{
+  fmt::Write,
+  iter::Peekable,
+  str::CharIndices,
+};
+
+/// Renders segments as markup.
+pub fn render(segments: &[Segment]) -> String {
+  Renderer::default().render(segments)
+}
+
+/// Renders segments as markup, naming the tags known to `schema`.
+pub fn render_with_schema(segments: &[Segment], schema: &TagSchema, encoding: Encoding, endianness: Endianness) -> String {
+  Renderer { schema: Some(SchemaContext { schema, encoding, endianness }) }.render(segments)
+}
+
+/// Parses markup into segments.
+pub fn parse(markup: &str) -> Result<Vec<Segment>> {
+  Parser::new(markup, None).parse()
+}
+
+/// Parses markup into segments, resolving named tags with `schema`.
+pub fn parse_with_schema(markup: &str, schema: &TagSchema, encoding: Encoding, endianness: Endianness) -> Result<Vec<Segment>> {
+  Parser::new(markup, Some(SchemaContext { schema, encoding, endianness })).parse()
+}
+
+#[derive(Clone, Copy)]
+struct SchemaContext<'a> {
+  schema: &'a TagSchema,
+  encoding: Encoding,
+  endianness: Endianness,
+}
+
+fn is_name_char(c: char) -> bool {
+  !c.is_whitespace() && !"[]=/\\\"".contains(c)
+}
+
+/// Checks if a name can be written bare and read back as a name rather than a number.
+fn is_plain_name(name: &str) -> bool {
+  name.chars().next().map(|c| !c.is_ascii_digit()).unwrap_or(false) && name.chars().all(is_name_char)
+}
+
+#[derive(Default)]
+struct Renderer<'a> {
+  schema: Option<SchemaContext<'a>>,
+}
+
+impl<'a> Renderer<'a> {
+  fn render(&self, segments: &[Segment]) -> String {
+    let mut out = String::new();
+    for segment in segments {
+      match *segment {
+        Segment::Text(ref text) => for c in text.chars() {
+          if c == '\\' || c == '[' {
+            out.push('\\');
+          }
+          out.push(c);
+        },
+        Segment::Tag { group, tag_type, ref params } => {
+          if !self.render_named_tag(&mut out, group, tag_type, params) {
+            write!(out, "[{}:{}", group, tag_type).expect("writing to string failed");
+            if !params.is_empty() {
+              out.push(' ');
+              for b in params {
+                write!(out, "{:02x}", b).expect("writing to string failed");
+              }
+            }
+            out.push(']');
+          }
+        },
+        Segment::TagEnd { group, tag_type } => match self.tag_name(group, tag_type) {
+          Some(name) => write!(out, "[/{}]", name).expect("writing to string failed"),
+          None => write!(out, "[/{}:{}]", group, tag_type).expect("writing to string failed"),
+        },
+        Segment::Null => out.push_str("\\0"),
+        Segment::Unterminated => out.push_str("\\$"),
+      }
+    }
+    out
+  }
+
+  /// Gets the name of a tag, if it can be written by name and read back as the same tag.
+  fn tag_name(&self, group: u16, tag_type: u16) -> Option<&'a str> {
+    let schema = self.schema?.schema;
+    let def = schema.get(group, tag_type)?;
+    let by_name = schema.get_by_name(&def.name)?;
+    if is_plain_name(&def.name) && (by_name.group, by_name.tag_type) == (group, tag_type) {
+      Some(&def.name)
+    } else {
+      None
+    }
+  }
+
+  fn render_named_tag(&self, out: &mut String, group: u16, tag_type: u16, params: &[u8]) -> bool {
+    let (ctx, name) = match (self.schema, self.tag_name(group, tag_type)) {
+      (Some(ctx), Some(name)) => (ctx, name),
+      _ => return false,
+    };
+    let values = match ctx.schema.decode(group, tag_type, params, ctx.encoding, ctx.endianness).params {
+      TagParams::Named(values) => values,
+      TagParams::Opaque(_) => return false,
+    };
+    if !values.iter().all(|(name, _)| is_plain_name(name)) {
+      return false;
+    }
+
+    out.push('[');
+    out.push_str(name);
+    for (name, value) in &values {
+      write!(out, " {}=", name).expect("writing to string failed");
+      match *value {
+        ParamValue::U8(x) => write!(out, "{}", x),
+        ParamValue::U16(x) => write!(out, "{}", x),
+        ParamValue::U32(x) => write!(out, "{}", x),
+        ParamValue::I8(x) => write!(out, "{}", x),
+        ParamValue::I16(x) => write!(out, "{}", x),
+        ParamValue::I32(x) => write!(out, "{}", x),
+        ParamValue::F32(x) => write!(out, "{:?}", x),
+        ParamValue::Enum(ref item) if is_plain_name(item) => write!(out, "{}", item),
+        ParamValue::String(ref s) | ParamValue::Enum(ref s) => {
+          out.push('"');
+          for c in s.chars() {
+            if c == '\\' || c == '"' {
+              out.push('\\');
+            }
+            out.push(c);
+          }
+          out.push('"');
+          Ok(())
+        },
+      }.expect("writing to string failed");
+    }
+    out.push(']');
+
+    true
+  }
+}
+
+struct Parser<'a> {
+  markup: &'a str,
+  chars: Peekable<CharIndices<'a>>,
+  schema: Option<SchemaContext<'a>>,
+}
+
+impl<'a> Parser<'a> {
+  fn new(markup: &'a str, schema: Option<SchemaContext<'a>>) -> Self {
+    Parser {
+      markup,
+      chars: markup.char_indices().peekable(),
+      schema,
+    }
+  }
+
+  fn parse(mut self) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+
+    while let Some((offset, c)) = self.chars.next() {
+      match c {
+        '\\' => match self.chars.next() {
+          Some((_, c @ '\\')) | Some((_, c @ '[')) => text.push(c),
+          Some((_, '0')) => {
+            if !text.is_empty() {
+              segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(Segment::Null);
+          },
+          Some((_, '$')) if self.chars.peek().is_none() => {
+            if !text.is_empty() {
+              segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(Segment::Unterminated);
+          },
+          Some((_, '$')) => return Err(self.error(offset, "\\$ must be at the end")),
+          _ => return Err(self.error(offset, "invalid escape sequence")),
+        },
+        '[' => {
+          if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(&mut text)));
+          }
+          segments.push(self.parse_tag(offset)?);
+        },
+        '\0' | '\u{e}' | '\u{f}' => return Err(self.error(offset, "control characters cannot be written as text")),
+        c => text.push(c),
+      }
+    }
+
+    if !text.is_empty() {
+      segments.push(Segment::Text(text));
+    }
+
+    Ok(segments)
+  }
+
+  fn parse_tag(&mut self, start: usize) -> Result<Segment> {
+    let closing = self.chars.next_if(|&(_, c)| c == '/').is_some();
+
+    if self.chars.peek().map(|&(_, c)| !c.is_ascii_digit()).unwrap_or(false) {
+      return self.parse_named_tag(start, closing);
+    }
+
+    let group = self.parse_number()?;
+    self.expect(':')?;
+    let tag_type = self.parse_number()?;
+
+    if closing {
+      self.expect(']')?;
+      return Ok(Segment::TagEnd { group, tag_type });
+    }
+
+    let mut params = Vec::new();
+    if self.chars.next_if(|&(_, c)| c == ' ').is_some() {
+      loop {
+        let (offset, high) = match self.chars.next() {
+          Some((_, ']')) => break,
+          Some((offset, c)) => (offset, c),
+          None => return Err(self.error(self.markup.len(), "unterminated tag")),
+        };
+        let low = match self.chars.next() {
+          Some((_, c)) => c,
+          None => return Err(self.error(self.markup.len(), "unterminated tag")),
+        };
+        match (high.to_digit(16), low.to_digit(16)) {
+          (Some(high), Some(low)) => params.push((high * 16 + low) as u8),
+          _ => return Err(self.error(offset, "invalid hex byte in tag parameters")),
+        }
+      }
+    } else {
+      self.expect(']')?;
+    }
+
+    Ok(Segment::Tag { group, tag_type, params })
+  }
+
+  fn parse_named_tag(&mut self, start: usize, closing: bool) -> Result<Segment> {
+    let name_offset = self.offset();
+    let name = self.parse_name()?;
+    let ctx = self.schema.ok_or_else(|| self.error(name_offset, "named tags require a schema"))?;
+    let def = ctx.schema.get_by_name(name).ok_or_else(|| self.error(name_offset, format!("unknown tag {:?}", name)))?;
+
+    if closing {
+      self.expect(']')?;
+      return Ok(Segment::TagEnd { group: def.group, tag_type: def.tag_type });
+    }
+
+    let mut values = Vec::new();
+    loop {
+      while self.chars.next_if(|&(_, c)| c.is_whitespace()).is_some() {}
+      if self.chars.next_if(|&(_, c)| c == ']').is_some() {
+        break;
+      }
+
+      let param_offset = self.offset();
+      let param_name = self.parse_name()?;
+      let param = def.params.iter()
+        .find(|p| p.name == param_name && p.kind != ParamType::Padding)
+        .ok_or_else(|| self.error(param_offset, format!("unknown parameter {:?} for tag {:?}", param_name, def.name)))?;
+      self.expect('=')?;
+
+      let value_offset = self.offset();
+      let (raw, quoted) = self.parse_value()?;
+      let invalid = |parser: &Self| parser.error(value_offset, format!("expected a value of type {}", param.kind.name()));
+      let value = match param.kind {
+        ParamType::String if quoted => ParamValue::String(raw),
+        ParamType::Enum { .. } => ParamValue::Enum(raw),
+        ParamType::String | ParamType::Padding => return Err(invalid(self)),
+        _ if quoted => return Err(invalid(self)),
+        ParamType::U8 => ParamValue::U8(raw.parse().map_err(|_| invalid(self))?),
+        ParamType::U16 => ParamValue::U16(raw.parse().map_err(|_| invalid(self))?),
+        ParamType::U32 => ParamValue::U32(raw.parse().map_err(|_| invalid(self))?),
+        ParamType::I8 => ParamValue::I8(raw.parse().map_err(|_| invalid(self))?),
+        ParamType::I16 => ParamValue::I16(raw.parse().map_err(|_| invalid(self))?),
+        ParamType::I32 => ParamValue::I32(raw.parse().map_err(|_| invalid(self))?),
+        ParamType::F32 => ParamValue::F32(raw.parse().map_err(|_| invalid(self))?),
+      };
+      values.push((param.name.clone(), value));
+    }
+
+    let params = def.encode_params(&values, ctx.encoding, ctx.endianness)
+      .map_err(|e| match e {
+        Error::InvalidTagParam { param, reason, .. } => self.error(start, format!("{} {:?}", reason, param)),
+        e => e,
+      })?;
+
+    Ok(Segment::Tag { group: def.group, tag_type: def.tag_type, params })
+  }
+
+  fn parse_name(&mut self) -> Result<&'a str> {
+    let start = self.offset();
+    let mut end = start;
+    while let Some((offset, c)) = self.chars.next_if(|&(_, c)| is_name_char(c)) {
+      end = offset + c.len_utf8();
+    }
+    if start == end {
+      return Err(self.error(start, "expected a name"));
+    }
+    Ok(&self.markup[start..end])
+  }
+
+  /// Parses a quoted string or a bare word, returning whether it was quoted.
+  fn parse_value(&mut self) -> Result<(String, bool)> {
+    if self.chars.next_if(|&(_, c)| c == '"').is_none() {
+      return self.parse_name().map(|s| (s.to_string(), false));
+    }
+
+    let mut value = String::new();
+    loop {
+      match self.chars.next() {
+        Some((_, '"')) => return Ok((value, true)),
+        Some((offset, '\\')) => match self.chars.next() {
+          Some((_, c @ '\\')) | Some((_, c @ '"')) => value.push(c),
+          _ => return Err(self.error(offset, "invalid escape sequence")),
+        },
+        Some((_, c)) => value.push(c),
+        None => return Err(self.error(self.markup.len(), "unterminated string")),
+      }
+    }
+  }
+
+  fn parse_number(&mut self) -> Result<u16> {
+    let start = self.offset();
+    let mut end = start;
+    while let Some((offset, c)) = self.chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+      end = offset + c.len_utf8();
+    }
+    if start == end {
+      return Err(self.error(start, "expected a number"));
+    }
+    self.markup[start..end].parse().map_err(|_| self.error(start, "number out of range"))
+  }
+
+  fn expect(&mut self, expected: char) -> Result<()> {
+    match self.chars.next() {
+      Some((_, c)) if c == expected => Ok(()),
+      Some((offset, _)) => Err(self.error(offset, format!("expected '{}'", expected))),
+      None => Err(self.error(self.markup.len(), "unterminated tag")),
+    }
+  }
+
+  fn offset(&mut self) -> usize {
+    self.chars.peek().map(|&(offset, _)| offset).unwrap_or_else(|| self.markup.len())
+  }
+
+  fn error<S: Into<String>>(&self, offset: usize, reason: S) -> Error {
+    let before = &self.markup[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map(|l| l.chars().count()).unwrap_or(0) + 1;
+    Error::InvalidMarkup {
+      line,
+      column,
+      reason: reason.into(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(raw: &[u8]) {
+    let segments = Segment::parse(raw, Encoding::Utf16, Endianness::Little).unwrap();
+    let markup = render(&segments);
+    assert_eq!(parse(&markup).unwrap(), segments, "markup {:?}", markup);
+    assert_eq!(Segment::encode(&parse(&markup).unwrap(), Encoding::Utf16, Endianness::Little).unwrap(), raw);
+  }
+
+  #[test]
+  fn round_trips_raw_strings() {
+    round_trip(&[72, 0, 105, 0, 0, 0]);
+    round_trip(&[72, 0, 105, 0]);
+    round_trip(&[]);
+    round_trip(&[0, 0]);
+    // a null before the terminator
+    round_trip(&[72, 0, 0, 0, 0, 0]);
+    round_trip(&[0, 0, 72, 0, 0, 0]);
+    // `\` and `[` need escaping
+    round_trip(&[92, 0, 91, 0, 0, 0]);
+    // a tag with parameters, text and a closing tag, without a terminator
+    round_trip(&[14, 0, 0, 0, 3, 0, 4, 0, 255, 0, 0, 0, 72, 0, 15, 0, 0, 0, 3, 0]);
+  }
+
+  #[test]
+  fn renders_tags() {
+    let segments = vec![
+      Segment::Tag { group: 0, tag_type: 3, params: vec![0xff, 0, 0, 0] },
+      Segment::Text("a[b\\".to_string()),
+      Segment::TagEnd { group: 0, tag_type: 3 },
+      Segment::Null,
+      Segment::Unterminated,
+    ];
+    assert_eq!(render(&segments), "[0:3 ff000000]a\\[b\\\\[/0:3]\\0\\$");
+    assert_eq!(parse("[0:3 ff000000]a\\[b\\\\[/0:3]\\0\\$").unwrap(), segments);
+  }
+
+  fn error_at(markup: &str) -> (usize, usize) {
+    match parse(markup) {
+      Err(Error::InvalidMarkup { line, column, .. }) => (line, column),
+      other => panic!("expected invalid markup, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn reports_error_locations() {
+    assert_eq!(error_at("abc\\x"), (1, 4));
+    assert_eq!(error_at("ab\ncd[0:3 zz]"), (2, 8));
+    assert_eq!(error_at("line\n\nxyz[1:2"), (3, 8));
+    assert_eq!(error_at("é[1;2]"), (1, 4));
+    assert_eq!(error_at("a\\$b"), (1, 2));
+    assert_eq!(error_at("[color]"), (1, 2));
+    assert_eq!(error_at("ab\u{e}"), (1, 3));
+  }
+}
@@ -0,0 +1,378 @@
+use crate::{
+  Encoding,
+  error::{Error, Result},
+};
+
+use byteordered::{Endian, Endianness};
+
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+/// The code unit that starts a control tag.
+pub const TAG_START: u16 = 0x0E;
+/// The code unit that starts a closing control tag.
+pub const TAG_END: u16 = 0x0F;
+
+/// A piece of a TXT2 message: either plain text or an inline control tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(rename_all = "snake_case"))]
+pub enum Segment {
+  /// Plain text, which cannot contain null, `0x0E` or `0x0F` characters.
+  Text(String),
+  /// A control tag (`0x0E`), with its raw parameter bytes.
+  Tag {
+    group: u16,
+    tag_type: u16,
+    params: Vec<u8>,
+  },
+  /// A closing control tag (`0x0F`).
+  TagEnd {
+    group: u16,
+    tag_type: u16,
+  },
+  /// A null code unit inside a string, before its end.
+  Null,
+  /// Ends a string that has no null terminator. It is ignored anywhere but the end of a string.
+  Unterminated,
+}
+
+impl Segment {
+  /// Splits a raw TXT2 string into text and control tags.
+  ///
+  /// A single trailing null terminator is removed. If there is none,
+  /// [`Unterminated`](Segment::Unterminated) is added instead, so that encoding the segments gives
+  /// back the same bytes. Any other null code unit becomes [`Null`](Segment::Null).
+  pub fn parse(raw: &[u8], encoding: Encoding, endianness: Endianness) -> Result<Vec<Segment>> {
+    Tokenizer::new(raw, encoding, endianness).tokenize()
+  }
+
+  /// Encodes segments into a raw TXT2 string, adding a null terminator unless they end with
+  /// [`Unterminated`](Segment::Unterminated).
+  ///
+  /// Fails if text contains a null, `0x0E` or `0x0F` character, which would be read back as
+  /// something else.
+  pub fn encode(segments: &[Segment], encoding: Encoding, endianness: Endianness) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    for segment in segments {
+      segment.encode_into(&mut buf, encoding, endianness)?;
+    }
+    if segments.last() != Some(&Segment::Unterminated) {
+      write_unit(&mut buf, 0, encoding, endianness);
+    }
+    Ok(buf)
+  }
+
+  fn encode_into(&self, buf: &mut Vec<u8>, encoding: Encoding, endianness: Endianness) -> Result<()> {
+    match *self {
+      Segment::Text(ref text) => {
+        if let Some(c) = text.chars().find(|&c| c == '\0' || c == '\u{e}' || c == '\u{f}') {
+          return Err(Error::InvalidTextChar(c));
+        }
+        buf.extend(encode_string(text, encoding, endianness));
+      },
+      Segment::Tag { group, tag_type, ref params } => {
+        if params.len() > u16::MAX as usize {
+          return Err(Error::TagParamsTooLong(params.len()));
+        }
+        write_unit(buf, TAG_START, encoding, endianness);
+        write_u16(buf, group, endianness);
+        write_u16(buf, tag_type, endianness);
+        write_u16(buf, params.len() as u16, endianness);
+        buf.extend_from_slice(params);
+      },
+      Segment::TagEnd { group, tag_type } => {
+        write_unit(buf, TAG_END, encoding, endianness);
+        write_u16(buf, group, endianness);
+        write_u16(buf, tag_type, endianness);
+      },
+      Segment::Null => write_unit(buf, 0, encoding, endianness),
+      Segment::Unterminated => {},
+    }
+    Ok(())
+  }
+}
+
+fn write_u16(buf: &mut Vec<u8>, value: u16, endianness: Endianness) {
+  endianness.write_u16(buf, value).expect("failed to write to vec");
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32, endianness: Endianness) {
+  endianness.write_u32(buf, value).expect("failed to write to vec");
+}
+
+fn write_unit(buf: &mut Vec<u8>, unit: u16, encoding: Encoding, endianness: Endianness) {
+  match encoding {
+    Encoding::Utf8 => buf.push(unit as u8),
+    Encoding::Utf16 => write_u16(buf, unit, endianness),
+    Encoding::Utf32 => write_u32(buf, u32::from(unit), endianness),
+  }
+}
+
+struct Tokenizer<'a> {
+  raw: &'a [u8],
+  encoding: Encoding,
+  endianness: Endianness,
+  pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+  fn new(raw: &'a [u8], encoding: Encoding, endianness: Endianness) -> Self {
+    Tokenizer {
+      raw,
+      encoding,
+      endianness,
+      pos: 0,
+    }
+  }
+
+  fn tokenize(mut self) -> Result<Vec<Segment>> {
+    let unit_size = self.encoding.unit_size();
+    if self.raw.len() % unit_size != 0 {
+      return Err(Error::UnexpectedEndOfMessage(self.raw.len()));
+    }
+
+    let mut segments = Vec::new();
+    let mut text_start = 0;
+
+    while self.pos < self.raw.len() {
+      let unit = self.read_unit()?;
+      if unit != 0 && unit != u32::from(TAG_START) && unit != u32::from(TAG_END) {
+        continue;
+      }
+
+      let unit_start = self.pos - unit_size;
+      if text_start < unit_start {
+        segments.push(Segment::Text(self.decode_text(&self.raw[text_start..unit_start])?));
+      }
+
+      // a null in the last unit is the terminator, and any other is part of the string
+      if unit == 0 {
+        if self.pos == self.raw.len() {
+          return Ok(segments);
+        }
+        segments.push(Segment::Null);
+        text_start = self.pos;
+        continue;
+      }
+
+      let group = self.read_u16()?;
+      let tag_type = self.read_u16()?;
+      if unit == u32::from(TAG_START) {
+        let param_size = self.read_u16()? as usize;
+        let params = self.read_bytes(param_size)?.to_vec();
+        segments.push(Segment::Tag { group, tag_type, params });
+      } else {
+        segments.push(Segment::TagEnd { group, tag_type });
+      }
+
+      text_start = self.pos;
+    }
+
+    if text_start < self.raw.len() {
+      segments.push(Segment::Text(self.decode_text(&self.raw[text_start..])?));
+    }
+    segments.push(Segment::Unterminated);
+
+    Ok(segments)
+  }
+
+  fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+    let raw = self.raw;
+    let bytes = raw.get(self.pos..self.pos + len).ok_or(Error::UnexpectedEndOfMessage(self.pos))?;
+    self.pos += len;
+    Ok(bytes)
+  }
+
+  fn read_u16(&mut self) -> Result<u16> {
+    let endianness = self.endianness;
+    self.read_bytes(2).map(|bs| endianness.read_u16(bs).expect("reading from slice failed"))
+  }
+
+  fn read_unit(&mut self) -> Result<u32> {
+    match self.encoding {
+      Encoding::Utf8 => self.read_bytes(1).map(|bs| u32::from(bs[0])),
+      Encoding::Utf16 => self.read_u16().map(u32::from),
+      Encoding::Utf32 => {
+        let endianness = self.endianness;
+        self.read_bytes(4).map(|bs| endianness.read_u32(bs).expect("reading from slice failed"))
+      },
+    }
+  }
+
+  fn decode_text(&self, bytes: &[u8]) -> Result<String> {
+    decode_string(bytes, self.encoding, self.endianness)
+  }
+}
+
+/// Decodes raw bytes in the given encoding into a string.
+pub(crate) fn decode_string(bytes: &[u8], encoding: Encoding, endianness: Endianness) -> Result<String> {
+  match encoding {
+    Encoding::Utf16 => {
+      let u16s = bytes.chunks(2)
+        .map(|bs| endianness.read_u16(bs).map_err(|_| Error::UnexpectedEndOfMessage(bytes.len())))
+        .collect::<Result<Vec<u16>>>()?;
+      String::from_utf16(&u16s).map_err(Error::InvalidUtf16)
+    },
+    Encoding::Utf32 => bytes.chunks(4)
+      .map(|bs| {
+        let c = endianness.read_u32(bs).map_err(|_| Error::UnexpectedEndOfMessage(bytes.len()))?;
+        std::char::from_u32(c).ok_or(Error::InvalidUtf32(c))
+      })
+      .collect(),
+    Encoding::Utf8 => String::from_utf8(bytes.to_vec()).map_err(Error::InvalidUtf8),
+  }
+}
+
+/// Encodes a string into raw bytes in the given encoding, without a null terminator.
+pub(crate) fn encode_string(string: &str, encoding: Encoding, endianness: Endianness) -> Vec<u8> {
+  match encoding {
+    Encoding::Utf16 => {
+      let mut buf = Vec::with_capacity(string.len() * 2);
+      for u in string.encode_utf16() {
+        write_u16(&mut buf, u, endianness);
+      }
+      buf
+    },
+    Encoding::Utf32 => {
+      let mut buf = Vec::with_capacity(string.len() * 4);
+      for c in string.chars() {
+        write_u32(&mut buf, c as u32, endianness);
+      }
+      buf
+    },
+    Encoding::Utf8 => string.as_bytes().to_vec(),
+  }
+}
+
+/// Encodes a message into raw bytes in the given encoding, adding a null terminator.
+pub(crate) fn encode_message(string: &str, encoding: Encoding, endianness: Endianness) -> Vec<u8> {
+  let mut buf = encode_string(string, encoding, endianness);
+  buf.extend(std::iter::repeat(0).take(encoding.unit_size()));
+  buf
+}
+
+/// Removes a single trailing null terminator from a raw message, if it has one.
+pub(crate) fn strip_terminator(raw: &[u8], encoding: Encoding) -> &[u8] {
+  let unit_size = encoding.unit_size();
+  match raw.len().checked_sub(unit_size) {
+    Some(end) if raw[end..].iter().all(|&b| b == 0) => &raw[..end],
+    _ => raw,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(raw: &[u8], encoding: Encoding, endianness: Endianness) -> Vec<Segment> {
+    let segments = Segment::parse(raw, encoding, endianness).unwrap();
+    assert_eq!(Segment::encode(&segments, encoding, endianness).unwrap(), raw);
+    segments
+  }
+
+  #[test]
+  fn parses_text_and_tags() {
+    let raw = [
+      72, 0, 14, 0, 1, 0, 2, 0, 2, 0, 10, 0, 105, 0, 15, 0, 1, 0, 2, 0, 0, 0,
+    ];
+    assert_eq!(round_trip(&raw, Encoding::Utf16, Endianness::Little), vec![
+      Segment::Text("H".to_string()),
+      Segment::Tag { group: 1, tag_type: 2, params: vec![10, 0] },
+      Segment::Text("i".to_string()),
+      Segment::TagEnd { group: 1, tag_type: 2 },
+    ]);
+  }
+
+  #[test]
+  fn round_trips_terminators() {
+    let le = Endianness::Little;
+    assert_eq!(round_trip(&[72, 0, 105, 0, 0, 0], Encoding::Utf16, le), vec![Segment::Text("Hi".to_string())]);
+    assert_eq!(round_trip(&[72, 0, 105, 0], Encoding::Utf16, le), vec![
+      Segment::Text("Hi".to_string()),
+      Segment::Unterminated,
+    ]);
+    assert_eq!(round_trip(&[0, 0], Encoding::Utf16, le), vec![]);
+    assert_eq!(round_trip(&[], Encoding::Utf16, le), vec![Segment::Unterminated]);
+    assert_eq!(round_trip(&[72, 0, 0, 0, 0, 0], Encoding::Utf16, le), vec![
+      Segment::Text("H".to_string()),
+      Segment::Null,
+    ]);
+    assert_eq!(round_trip(&[0, b'a', 0], Encoding::Utf8, le), vec![
+      Segment::Null,
+      Segment::Text("a".to_string()),
+    ]);
+    assert_eq!(round_trip(&[b'a', 0], Encoding::Utf8, le), vec![Segment::Text("a".to_string())]);
+    assert_eq!(round_trip(&[0, 0, 0, b'a'], Encoding::Utf32, Endianness::Big), vec![
+      Segment::Text("a".to_string()),
+      Segment::Unterminated,
+    ]);
+  }
+
+  #[test]
+  fn keeps_trailing_zero_tag_params() {
+    // a tag ending the string whose parameters end in zeros, with no terminator after it
+    let raw = [14, 0, 0, 0, 3, 0, 2, 0, 0, 0];
+    assert_eq!(round_trip(&raw, Encoding::Utf16, Endianness::Little), vec![
+      Segment::Tag { group: 0, tag_type: 3, params: vec![0, 0] },
+      Segment::Unterminated,
+    ]);
+  }
+
+  #[test]
+  fn rejects_truncated_tags() {
+    assert!(matches!(
+      Segment::parse(&[14, 0, 0, 0, 3, 0, 4, 0, 1, 0], Encoding::Utf16, Endianness::Little),
+      Err(Error::UnexpectedEndOfMessage(_)),
+    ));
+  }
+
+  #[test]
+  fn rejects_partial_code_units() {
+    assert!(matches!(
+      decode_string(&[65, 0, 66], Encoding::Utf16, Endianness::Little),
+      Err(Error::UnexpectedEndOfMessage(3)),
+    ));
+    assert!(matches!(
+      decode_string(&[65, 0, 0, 0, 66], Encoding::Utf32, Endianness::Little),
+      Err(Error::UnexpectedEndOfMessage(5)),
+    ));
+  }
+
+  #[test]
+  fn encodes_in_each_encoding() {
+    let segments = [
+      Segment::Text("é".to_string()),
+      Segment::TagEnd { group: 1, tag_type: 2 },
+    ];
+    assert_eq!(Segment::encode(&segments, Encoding::Utf8, Endianness::Little).unwrap(), [
+      0xc3, 0xa9, 15, 1, 0, 2, 0, 0,
+    ]);
+    assert_eq!(Segment::encode(&segments, Encoding::Utf16, Endianness::Big).unwrap(), [
+      0, 0xe9, 0, 15, 0, 1, 0, 2, 0, 0,
+    ]);
+    assert_eq!(Segment::encode(&segments, Encoding::Utf32, Endianness::Little).unwrap(), [
+      0xe9, 0, 0, 0, 15, 0, 0, 0, 1, 0, 2, 0, 0, 0, 0, 0,
+    ]);
+  }
+
+  #[test]
+  fn rejects_control_characters_in_text() {
+    for &c in &['\0', '\u{e}', '\u{f}'] {
+      let segments = [Segment::Text(format!("a{}b", c))];
+      assert!(matches!(
+        Segment::encode(&segments, Encoding::Utf16, Endianness::Little),
+        Err(Error::InvalidTextChar(x)) if x == c,
+      ));
+    }
+  }
+
+  #[test]
+  fn rejects_long_tag_params() {
+    let segments = [Segment::Tag { group: 0, tag_type: 0, params: vec![0; 0x10000] }];
+    assert!(matches!(
+      Segment::encode(&segments, Encoding::Utf16, Endianness::Little),
+      Err(Error::TagParamsTooLong(0x10000)),
+    ));
+  }
+}
@@ -0,0 +1,1000 @@
+pub mod ali2;
+pub mod ati2;
+pub mod builder;
+pub mod clr1;
+pub mod cti1;
+pub mod label_table;
+pub mod syl3;
+pub mod tag2;
+pub mod tgg2;
+pub mod tgl2;
+pub mod tgp2;
+
+pub use self::{
+  ali2::Ali2,
+  ati2::Ati2,
+  clr1::Clr1,
+  cti1::Cti1,
+  label_table::LabelTable,
+  syl3::Syl3,
+  tag2::Tag2,
+  tgg2::Tgg2,
+  tgl2::Tgl2,
+  tgp2::Tgp2,
+};
+
+use crate::{
+  Header,
+  PADDING_CHAR,
+  PADDING_LENGTH,
+  counter::Counter,
+  error::{Error, Result},
+  schema::{ParamDef, ParamType, TagDef, TagSchema},
+  section::{Section, lbl1::Group},
+  traits::{CalculatesSize, Updates},
+};
+use self::{
+  ati2::{AttributeDef, AttributeInfo},
+  clr1::Color,
+  label_table::LabelEntry,
+  syl3::Style,
+  tag2::TagInfo,
+  tgg2::TagGroup,
+  tgp2::TagParam,
+};
+
+use byteordered::{Endian, Endianness};
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub(crate) const MSBP_MAGIC: [u8; 8] = *b"MsgPrjBn";
+
+/// The value types used by attributes in ATI2 and tag parameters in TGP2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+  U8 = 0,
+  U16 = 1,
+  U32 = 2,
+  I8 = 3,
+  I16 = 4,
+  I32 = 5,
+  F32 = 6,
+  /// An offset into the string table following the ATR1 records, or a length-prefixed string in
+  /// tag parameters.
+  String = 8,
+  /// A u8 index into a list of named items.
+  List = 9,
+}
+
+impl ValueType {
+  pub fn from_u8(value: u8) -> Option<Self> {
+    let value_type = match value {
+      0 => ValueType::U8,
+      1 => ValueType::U16,
+      2 => ValueType::U32,
+      3 => ValueType::I8,
+      4 => ValueType::I16,
+      5 => ValueType::I32,
+      6 => ValueType::F32,
+      8 => ValueType::String,
+      9 => ValueType::List,
+      _ => return None,
+    };
+    Some(value_type)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsbpSectionTag {
+  Clr1,
+  Clb1,
+  Ati2,
+  Alb1,
+  Ali2,
+  Tgg2,
+  Tag2,
+  Tgp2,
+  Tgl2,
+  Syl3,
+  Slb1,
+  Cti1,
+}
+
+/// An MSBP project file, holding the colours, attributes, tags and styles used by MSBT files.
+#[derive(Debug)]
+pub struct Msbp {
+  pub(crate) header: Header,
+  pub(crate) section_order: Vec<MsbpSectionTag>,
+  pub(crate) clr1: Option<Clr1>,
+  pub(crate) clb1: Option<LabelTable>,
+  pub(crate) ati2: Option<Ati2>,
+  pub(crate) alb1: Option<LabelTable>,
+  pub(crate) ali2: Option<Ali2>,
+  pub(crate) tgg2: Option<Tgg2>,
+  pub(crate) tag2: Option<Tag2>,
+  pub(crate) tgp2: Option<Tgp2>,
+  pub(crate) tgl2: Option<Tgl2>,
+  pub(crate) syl3: Option<Syl3>,
+  pub(crate) slb1: Option<LabelTable>,
+  pub(crate) cti1: Option<Cti1>,
+}
+
+impl Msbp {
+  pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self> {
+    MsbpReader::new(reader).map(MsbpReader::into_msbp)
+  }
+
+  pub fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+    MsbpWriter::new(self, writer).write()
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  pub fn section_order(&self) -> &[MsbpSectionTag] {
+    &self.section_order
+  }
+
+  pub fn clr1(&self) -> Option<&Clr1> {
+    self.clr1.as_ref()
+  }
+
+  pub fn clb1(&self) -> Option<&LabelTable> {
+    self.clb1.as_ref()
+  }
+
+  pub fn ati2(&self) -> Option<&Ati2> {
+    self.ati2.as_ref()
+  }
+
+  pub fn alb1(&self) -> Option<&LabelTable> {
+    self.alb1.as_ref()
+  }
+
+  pub fn ali2(&self) -> Option<&Ali2> {
+    self.ali2.as_ref()
+  }
+
+  pub fn tgg2(&self) -> Option<&Tgg2> {
+    self.tgg2.as_ref()
+  }
+
+  pub fn tag2(&self) -> Option<&Tag2> {
+    self.tag2.as_ref()
+  }
+
+  pub fn tgp2(&self) -> Option<&Tgp2> {
+    self.tgp2.as_ref()
+  }
+
+  pub fn tgl2(&self) -> Option<&Tgl2> {
+    self.tgl2.as_ref()
+  }
+
+  pub fn syl3(&self) -> Option<&Syl3> {
+    self.syl3.as_ref()
+  }
+
+  pub fn slb1(&self) -> Option<&LabelTable> {
+    self.slb1.as_ref()
+  }
+
+  pub fn cti1(&self) -> Option<&Cti1> {
+    self.cti1.as_ref()
+  }
+
+  /// Gets a colour by its label in CLB1.
+  pub fn color(&self, name: &str) -> Option<Color> {
+    let index = self.clb1.as_ref()?.index_of(name)?;
+    self.clr1.as_ref()?.colors.get(index as usize).copied()
+  }
+
+  /// Resolves every attribute in ATI2 with its name and list items.
+  ///
+  /// Attributes without a label in ALB1 or with an unknown type are left out.
+  pub fn attribute_defs(&self) -> Vec<AttributeDef> {
+    let (ati2, alb1) = match (&self.ati2, &self.alb1) {
+      (Some(ati2), Some(alb1)) => (ati2, alb1),
+      _ => return Vec::new(),
+    };
+    let lists = self.ali2.as_ref().map(|x| x.lists.as_slice()).unwrap_or_default();
+
+    ati2.attributes
+      .iter()
+      .enumerate()
+      .filter_map(|(i, info)| {
+        let value_type = ValueType::from_u8(info.attr_type)?;
+        let items = if value_type == ValueType::List {
+          lists.get(info.list_index as usize)?.clone()
+        } else {
+          Vec::new()
+        };
+        Some(AttributeDef {
+          name: alb1.name_of(i as u32)?.to_string(),
+          value_type,
+          offset: info.offset,
+          items,
+        })
+      })
+      .collect()
+  }
+
+  /// Builds a tag schema from the tag groups, tags, parameters and list items in TGG2, TAG2, TGP2
+  /// and TGL2.
+  ///
+  /// Tags referring to missing entries or using unknown parameter types are left out, so their
+  /// parameters are kept as opaque bytes.
+  pub fn tag_schema(&self) -> TagSchema {
+    let (tgg2, tag2, tgp2) = match (&self.tgg2, &self.tag2, &self.tgp2) {
+      (Some(tgg2), Some(tag2), Some(tgp2)) => (tgg2, tag2, tgp2),
+      _ => return TagSchema::new(),
+    };
+    let items = self.tgl2.as_ref().map(|x| x.items.as_slice()).unwrap_or_default();
+
+    let param_def = |index: u16| -> Option<ParamDef> {
+      let param = tgp2.params.get(index as usize)?;
+      let kind = match ValueType::from_u8(param.param_type)? {
+        ValueType::U8 => ParamType::U8,
+        ValueType::U16 => ParamType::U16,
+        ValueType::U32 => ParamType::U32,
+        ValueType::I8 => ParamType::I8,
+        ValueType::I16 => ParamType::I16,
+        ValueType::I32 => ParamType::I32,
+        ValueType::F32 => ParamType::F32,
+        ValueType::String => ParamType::String,
+        ValueType::List => ParamType::Enum {
+          values: param.list_item_indices
+            .iter()
+            .map(|&i| items.get(i as usize).cloned())
+            .collect::<Option<_>>()?,
+        },
+      };
+      Some(ParamDef::new(param.name.clone(), kind))
+    };
+
+    tgg2.groups
+      .iter()
+      .flat_map(|group| group.tag_indices
+        .iter()
+        .enumerate()
+        .map(move |(tag_type, &index)| (group.id, tag_type as u16, index)))
+      .filter_map(|(group, tag_type, index)| {
+        let tag = tag2.tags.get(index as usize)?;
+        let params = tag.param_indices
+          .iter()
+          .map(|&i| param_def(i))
+          .collect::<Option<_>>()?;
+        Some(TagDef::new(group, tag_type, tag.name.clone(), params))
+      })
+      .collect()
+  }
+
+  /// Gets a style by its label in SLB1.
+  pub fn style(&self, name: &str) -> Option<Style> {
+    let index = self.slb1.as_ref()?.index_of(name)?;
+    self.syl3.as_ref()?.styles.get(index as usize).copied()
+  }
+}
+
+impl Updates for Msbp {
+  fn update(&mut self) {
+    for table in [&mut self.clb1, &mut self.alb1, &mut self.slb1].iter_mut().filter_map(|t| t.as_mut()) {
+      table.update_groups();
+    }
+
+    // sections that cannot be written keep their old size, and fail when writing the file
+    let writer = MsbpWriter::new(self, std::io::sink());
+    let sizes: Vec<(MsbpSectionTag, usize)> = self.section_order
+      .iter()
+      .filter_map(|&tag| writer.section_body(tag).ok().flatten().map(|(_, body)| (tag, body.len())))
+      .collect();
+
+    let mut file_size = self.header.calc_file_size();
+    for &(tag, size) in &sizes {
+      let section = match tag {
+        MsbpSectionTag::Clr1 => self.clr1.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Clb1 => self.clb1.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Ati2 => self.ati2.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Alb1 => self.alb1.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Ali2 => self.ali2.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Tgg2 => self.tgg2.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Tag2 => self.tag2.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Tgp2 => self.tgp2.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Tgl2 => self.tgl2.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Syl3 => self.syl3.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Slb1 => self.slb1.as_mut().map(|x| &mut x.section),
+        MsbpSectionTag::Cti1 => self.cti1.as_mut().map(|x| &mut x.section),
+      };
+      if let Some(section) = section {
+        section.size = size as u32;
+        file_size += crate::Msbt::plus_padding(section.calc_size() + size);
+      }
+    }
+
+    self.header.section_count = sizes.len() as u16;
+    self.header.file_size = file_size as u32;
+  }
+}
+
+#[derive(Debug)]
+pub struct MsbpReader<R> {
+  reader: R,
+  msbp: Msbp,
+}
+
+impl<R: Read + Seek> MsbpReader<R> {
+  fn new(mut reader: R) -> Result<Self> {
+    let header = Header::from_reader_with_magic(&mut reader, MSBP_MAGIC)?;
+
+    let mut msbp = MsbpReader {
+      reader,
+      msbp: Msbp {
+        header,
+        section_order: Vec::with_capacity(12),
+        clr1: None,
+        clb1: None,
+        ati2: None,
+        alb1: None,
+        ali2: None,
+        tgg2: None,
+        tag2: None,
+        tgp2: None,
+        tgl2: None,
+        syl3: None,
+        slb1: None,
+        cti1: None,
+      },
+    };
+
+    msbp.read_sections()?;
+
+    Ok(msbp)
+  }
+
+  fn into_msbp(self) -> Msbp {
+    self.msbp
+  }
+
+  fn skip_padding(&mut self) -> Result<()> {
+    let mut buf = [0; 16];
+    loop {
+      let read = self.reader.read(&mut buf).map_err(Error::Io)?;
+      if read == 0 {
+        return Ok(());
+      }
+      if let Some(i) = buf[..read].iter().position(|&x| x != PADDING_CHAR) {
+        self.reader.seek(SeekFrom::Current(i as i64 - read as i64)).map_err(Error::Io)?;
+        return Ok(());
+      }
+    }
+  }
+
+  pub fn read_sections(&mut self) -> Result<()> {
+    loop {
+      let section = match Section::from_reader(&mut self.reader, self.msbp.header.endianness) {
+        Ok(section) => section,
+        Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+        Err(e) => return Err(e),
+      };
+
+      let mut data = vec![0; section.size as usize];
+      self.reader.read_exact(&mut data).map_err(Error::Io)?;
+      let body = Body {
+        data: &data,
+        endianness: self.msbp.header.endianness,
+      };
+
+      let msbp = &mut self.msbp;
+      let tag = match &section.magic {
+        b"CLR1" => {
+          msbp.clr1 = Some(body.read_clr1(section)?);
+          MsbpSectionTag::Clr1
+        },
+        b"CLB1" => {
+          msbp.clb1 = Some(body.read_label_table(section)?);
+          MsbpSectionTag::Clb1
+        },
+        b"ATI2" => {
+          msbp.ati2 = Some(body.read_ati2(section)?);
+          MsbpSectionTag::Ati2
+        },
+        b"ALB1" => {
+          msbp.alb1 = Some(body.read_label_table(section)?);
+          MsbpSectionTag::Alb1
+        },
+        b"ALI2" => {
+          msbp.ali2 = Some(body.read_ali2(section)?);
+          MsbpSectionTag::Ali2
+        },
+        b"TGG2" => {
+          msbp.tgg2 = Some(body.read_tgg2(section)?);
+          MsbpSectionTag::Tgg2
+        },
+        b"TAG2" => {
+          msbp.tag2 = Some(body.read_tag2(section)?);
+          MsbpSectionTag::Tag2
+        },
+        b"TGP2" => {
+          msbp.tgp2 = Some(body.read_tgp2(section)?);
+          MsbpSectionTag::Tgp2
+        },
+        b"TGL2" => {
+          msbp.tgl2 = Some(body.read_tgl2(section)?);
+          MsbpSectionTag::Tgl2
+        },
+        b"SYL3" => {
+          msbp.syl3 = Some(body.read_syl3(section)?);
+          MsbpSectionTag::Syl3
+        },
+        b"SLB1" => {
+          msbp.slb1 = Some(body.read_label_table(section)?);
+          MsbpSectionTag::Slb1
+        },
+        b"CTI1" => {
+          msbp.cti1 = Some(body.read_cti1(section)?);
+          MsbpSectionTag::Cti1
+        },
+        _ => return Err(Error::InvalidSection(section.magic)),
+      };
+      msbp.section_order.push(tag);
+
+      self.skip_padding()?;
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct MsbpWriter<'a, W> {
+  writer: Counter<W>,
+  msbp: &'a Msbp,
+}
+
+impl<'a, W: Write> MsbpWriter<'a, W> {
+  fn new(msbp: &'a Msbp, writer: W) -> Self {
+    MsbpWriter {
+      msbp,
+      writer: Counter::new(writer),
+    }
+  }
+
+  fn write(&mut self) -> Result<()> {
+    let sections: Vec<(&Section, Vec<u8>)> = self.msbp.section_order
+      .iter()
+      .filter_map(|&tag| self.section_body(tag).transpose())
+      .collect::<Result<_>>()?;
+
+    let file_size = self.msbp.header.calc_file_size()
+      + sections.iter().map(|(section, body)| crate::Msbt::plus_padding(section.calc_size() + body.len())).sum::<usize>();
+    self.msbp.header.write_to(&mut self.writer, file_size as u32)?;
+
+    for (section, body) in sections {
+      self.writer.write_all(&section.magic).map_err(Error::Io)?;
+      self.msbp.header.endianness.write_u32(&mut self.writer, body.len() as u32).map_err(Error::Io)?;
+      self.writer.write_all(&section.padding).map_err(Error::Io)?;
+      self.writer.write_all(&body).map_err(Error::Io)?;
+      self.write_padding()?;
+    }
+
+    Ok(())
+  }
+
+  fn section_body(&self, tag: MsbpSectionTag) -> Result<Option<(&'a Section, Vec<u8>)>> {
+    let msbp = self.msbp;
+    let mut body = BodyWriter {
+      data: Vec::new(),
+      endianness: msbp.header.endianness,
+    };
+    let section = match tag {
+      MsbpSectionTag::Clr1 => msbp.clr1.as_ref().map(|x| body.write_clr1(x)),
+      MsbpSectionTag::Clb1 => msbp.clb1.as_ref().map(|x| body.write_label_table(x)).transpose()?,
+      MsbpSectionTag::Ati2 => msbp.ati2.as_ref().map(|x| body.write_ati2(x)),
+      MsbpSectionTag::Alb1 => msbp.alb1.as_ref().map(|x| body.write_label_table(x)).transpose()?,
+      MsbpSectionTag::Ali2 => msbp.ali2.as_ref().map(|x| body.write_ali2(x)),
+      MsbpSectionTag::Tgg2 => msbp.tgg2.as_ref().map(|x| body.write_tgg2(x)),
+      MsbpSectionTag::Tag2 => msbp.tag2.as_ref().map(|x| body.write_tag2(x)),
+      MsbpSectionTag::Tgp2 => msbp.tgp2.as_ref().map(|x| body.write_tgp2(x)),
+      MsbpSectionTag::Tgl2 => msbp.tgl2.as_ref().map(|x| body.write_tgl2(x)),
+      MsbpSectionTag::Syl3 => msbp.syl3.as_ref().map(|x| body.write_syl3(x)),
+      MsbpSectionTag::Slb1 => msbp.slb1.as_ref().map(|x| body.write_label_table(x)).transpose()?,
+      MsbpSectionTag::Cti1 => msbp.cti1.as_ref().map(|x| body.write_cti1(x)),
+    };
+    Ok(section.map(|section| (section, body.data)))
+  }
+
+  fn write_padding(&mut self) -> Result<()> {
+    let remainder = self.writer.written() % PADDING_LENGTH;
+    if remainder == 0 {
+      return Ok(());
+    }
+
+    self.writer.write_all(&vec![PADDING_CHAR; PADDING_LENGTH - remainder]).map_err(Error::Io)
+  }
+}
+
+/// Builds the body of a section in memory, so that its internal offsets are known before it is
+/// written.
+struct BodyWriter {
+  data: Vec<u8>,
+  endianness: Endianness,
+}
+
+impl BodyWriter {
+  fn u8(&mut self, value: u8) {
+    self.data.push(value);
+  }
+
+  fn u16(&mut self, value: u16) {
+    self.endianness.write_u16(&mut self.data, value).expect("failed to write to vec");
+  }
+
+  fn u32(&mut self, value: u32) {
+    self.endianness.write_u32(&mut self.data, value).expect("failed to write to vec");
+  }
+
+  fn i32(&mut self, value: i32) {
+    self.endianness.write_i32(&mut self.data, value).expect("failed to write to vec");
+  }
+
+  /// Writes a null-terminated string.
+  fn string(&mut self, value: &str) {
+    self.data.extend_from_slice(value.as_bytes());
+    self.data.push(0);
+  }
+
+  fn align(&mut self, alignment: usize) {
+    while self.data.len() % alignment != 0 {
+      self.data.push(0);
+    }
+  }
+
+  /// Writes an offset table followed by its entries, each aligned to `alignment`.
+  ///
+  /// `count_size` is the size of the entry count, which is followed by padding up to four bytes.
+  fn table<T, F>(&mut self, count_size: usize, items: &[T], alignment: usize, mut write_item: F)
+    where F: FnMut(&mut BodyWriter, &T),
+  {
+    match count_size {
+      2 => {
+        self.u16(items.len() as u16);
+        self.u16(0);
+      },
+      _ => self.u32(items.len() as u32),
+    }
+
+    let offsets_pos = self.data.len();
+    self.data.resize(offsets_pos + items.len() * 4, 0);
+
+    for (i, item) in items.iter().enumerate() {
+      self.align(alignment);
+      let offset = self.data.len() as u32;
+      self.endianness
+        .write_u32(&mut self.data[offsets_pos + i * 4..offsets_pos + i * 4 + 4], offset)
+        .expect("failed to write to vec");
+      write_item(self, item);
+    }
+  }
+
+  fn write_clr1<'a>(&mut self, clr1: &'a Clr1) -> &'a Section {
+    self.u32(clr1.colors.len() as u32);
+    for color in &clr1.colors {
+      self.data.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+    &clr1.section
+  }
+
+  fn write_label_table<'a>(&mut self, table: &'a LabelTable) -> Result<&'a Section> {
+    let mut sorted_labels: Vec<&LabelEntry> = table.labels.iter().collect();
+    sorted_labels.sort_by_key(|l| l.checksum);
+
+    self.u32(table.group_count);
+    for group in &table.groups {
+      self.u32(group.label_count);
+      self.u32(group.offset);
+    }
+
+    for label in sorted_labels {
+      if label.name.len() > u8::MAX as usize {
+        return Err(Error::LabelTooLong(label.name.clone()));
+      }
+      self.u8(label.name.len() as u8);
+      self.data.extend_from_slice(label.name.as_bytes());
+      self.u32(label.index);
+    }
+    Ok(&table.section)
+  }
+
+  fn write_ati2<'a>(&mut self, ati2: &'a Ati2) -> &'a Section {
+    self.u32(ati2.attributes.len() as u32);
+    for attribute in &ati2.attributes {
+      self.u8(attribute.attr_type);
+      self.u8(attribute._unknown_1);
+      self.u16(attribute.list_index);
+      self.u32(attribute.offset);
+    }
+    &ati2.section
+  }
+
+  fn write_ali2<'a>(&mut self, ali2: &'a Ali2) -> &'a Section {
+    self.table(4, &ali2.lists, 4, |body, items| {
+      let list_start = body.data.len();
+      body.table(4, items, 1, |body, item| body.string(item));
+      // item offsets are relative to the start of the list
+      for i in 0..items.len() {
+        let pos = list_start + 4 + i * 4;
+        let offset = body.endianness.read_u32(&body.data[pos..pos + 4]).expect("reading from vec failed");
+        body.endianness
+          .write_u32(&mut body.data[pos..pos + 4], offset - list_start as u32)
+          .expect("failed to write to vec");
+      }
+    });
+    &ali2.section
+  }
+
+  fn write_tgg2<'a>(&mut self, tgg2: &'a Tgg2) -> &'a Section {
+    self.table(2, &tgg2.groups, 2, |body, group| {
+      body.u16(group.id);
+      body.u16(group.tag_indices.len() as u16);
+      for &index in &group.tag_indices {
+        body.u16(index);
+      }
+      body.string(&group.name);
+    });
+    &tgg2.section
+  }
+
+  fn write_tag2<'a>(&mut self, tag2: &'a Tag2) -> &'a Section {
+    self.table(2, &tag2.tags, 2, |body, tag| {
+      body.u16(tag.param_indices.len() as u16);
+      for &index in &tag.param_indices {
+        body.u16(index);
+      }
+      body.string(&tag.name);
+    });
+    &tag2.section
+  }
+
+  fn write_tgp2<'a>(&mut self, tgp2: &'a Tgp2) -> &'a Section {
+    self.table(2, &tgp2.params, 2, |body, param| {
+      body.u8(param.param_type);
+      if param.param_type == TagParam::LIST_TYPE {
+        body.u8(0);
+        body.u16(param.list_item_indices.len() as u16);
+        for &index in &param.list_item_indices {
+          body.u16(index);
+        }
+      }
+      body.string(&param.name);
+    });
+    &tgp2.section
+  }
+
+  fn write_tgl2<'a>(&mut self, tgl2: &'a Tgl2) -> &'a Section {
+    self.table(2, &tgl2.items, 1, |body, item| body.string(item));
+    &tgl2.section
+  }
+
+  fn write_syl3<'a>(&mut self, syl3: &'a Syl3) -> &'a Section {
+    self.u32(syl3.styles.len() as u32);
+    for style in &syl3.styles {
+      self.u32(style.region_width);
+      self.u32(style.line_count);
+      self.u32(style.font_index);
+      self.i32(style.base_color_index);
+    }
+    &syl3.section
+  }
+
+  fn write_cti1<'a>(&mut self, cti1: &'a Cti1) -> &'a Section {
+    self.table(4, &cti1.filenames, 1, |body, filename| body.string(filename));
+    &cti1.section
+  }
+}
+
+/// The body of a section, read into memory so that its internal offsets can be followed.
+///
+/// Offsets are relative to the start of the body.
+struct Body<'a> {
+  data: &'a [u8],
+  endianness: Endianness,
+}
+
+impl<'a> Body<'a> {
+  fn bytes(&self, pos: usize, len: usize) -> Result<&'a [u8]> {
+    self.data
+      .get(pos..pos + len)
+      .ok_or_else(|| Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
+  }
+
+  fn u8(&self, pos: usize) -> Result<u8> {
+    self.bytes(pos, 1).map(|bs| bs[0])
+  }
+
+  fn u16(&self, pos: usize) -> Result<u16> {
+    self.endianness.read_u16(self.bytes(pos, 2)?).map_err(Error::Io)
+  }
+
+  fn u32(&self, pos: usize) -> Result<u32> {
+    self.endianness.read_u32(self.bytes(pos, 4)?).map_err(Error::Io)
+  }
+
+  fn i32(&self, pos: usize) -> Result<i32> {
+    self.endianness.read_i32(self.bytes(pos, 4)?).map_err(Error::Io)
+  }
+
+  /// Reads a null-terminated string.
+  fn string(&self, pos: usize) -> Result<String> {
+    let rest = self.data.get(pos..).unwrap_or_default();
+    let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+    String::from_utf8(rest[..len].to_vec()).map_err(Error::InvalidUtf8)
+  }
+
+  fn u16_list(&self, pos: usize, count: usize) -> Result<Vec<u16>> {
+    (0..count).map(|i| self.u16(pos + i * 2)).collect()
+  }
+
+  /// Reads the offset table found at the start of most sections.
+  ///
+  /// `count_size` is the size of the entry count, which is followed by padding up to four bytes.
+  fn offsets(&self, count_size: usize) -> Result<Vec<usize>> {
+    let count = match count_size {
+      2 => self.u16(0)? as usize,
+      _ => self.u32(0)? as usize,
+    };
+    (0..count).map(|i| self.u32(4 + i * 4).map(|x| x as usize)).collect()
+  }
+
+  fn read_clr1(&self, section: Section) -> Result<Clr1> {
+    let count = self.u32(0)? as usize;
+    let colors = (0..count)
+      .map(|i| self.bytes(4 + i * 4, 4).map(|c| Color { r: c[0], g: c[1], b: c[2], a: c[3] }))
+      .collect::<Result<_>>()?;
+
+    Ok(Clr1 {
+      section,
+      colors,
+    })
+  }
+
+  fn read_label_table(&self, section: Section) -> Result<LabelTable> {
+    let group_count = self.u32(0)?;
+
+    let groups = (0..group_count as usize)
+      .map(|i| Ok(Group {
+        label_count: self.u32(4 + i * 8)?,
+        offset: self.u32(8 + i * 8)?,
+      }))
+      .collect::<Result<Vec<_>>>()?;
+
+    // each label takes at least five bytes, which bounds the count a damaged table can claim
+    let label_count = groups.iter().map(|x| x.label_count as usize).sum::<usize>();
+    let mut labels = Vec::with_capacity(label_count.min(self.data.len() / 5));
+    for (i, group) in groups.iter().enumerate() {
+      let mut pos = group.offset as usize;
+      for _ in 0..group.label_count {
+        let len = self.u8(pos)? as usize;
+        let name = String::from_utf8(self.bytes(pos + 1, len)?.to_vec()).map_err(Error::InvalidUtf8)?;
+        let index = self.u32(pos + 1 + len)?;
+        pos += 1 + len + 4;
+
+        labels.push(LabelEntry {
+          name,
+          index,
+          checksum: i as u32,
+        });
+      }
+    }
+
+    Ok(LabelTable {
+      section,
+      group_count,
+      groups,
+      labels,
+    })
+  }
+
+  fn read_ati2(&self, section: Section) -> Result<Ati2> {
+    let count = self.u32(0)? as usize;
+    let attributes = (0..count)
+      .map(|i| {
+        let pos = 4 + i * 8;
+        Ok(AttributeInfo {
+          attr_type: self.u8(pos)?,
+          _unknown_1: self.u8(pos + 1)?,
+          list_index: self.u16(pos + 2)?,
+          offset: self.u32(pos + 4)?,
+        })
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Ati2 {
+      section,
+      attributes,
+    })
+  }
+
+  fn read_ali2(&self, section: Section) -> Result<Ali2> {
+    let lists = self.offsets(4)?
+      .into_iter()
+      .map(|list_pos| {
+        let count = self.u32(list_pos)? as usize;
+        (0..count)
+          .map(|i| self.string(list_pos + self.u32(list_pos + 4 + i * 4)? as usize))
+          .collect()
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Ali2 {
+      section,
+      lists,
+    })
+  }
+
+  fn read_tgg2(&self, section: Section) -> Result<Tgg2> {
+    let groups = self.offsets(2)?
+      .into_iter()
+      .map(|pos| {
+        let id = self.u16(pos)?;
+        let count = self.u16(pos + 2)? as usize;
+        Ok(TagGroup {
+          id,
+          tag_indices: self.u16_list(pos + 4, count)?,
+          name: self.string(pos + 4 + count * 2)?,
+        })
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Tgg2 {
+      section,
+      groups,
+    })
+  }
+
+  fn read_tag2(&self, section: Section) -> Result<Tag2> {
+    let tags = self.offsets(2)?
+      .into_iter()
+      .map(|pos| {
+        let count = self.u16(pos)? as usize;
+        Ok(TagInfo {
+          param_indices: self.u16_list(pos + 2, count)?,
+          name: self.string(pos + 2 + count * 2)?,
+        })
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Tag2 {
+      section,
+      tags,
+    })
+  }
+
+  fn read_tgp2(&self, section: Section) -> Result<Tgp2> {
+    let params = self.offsets(2)?
+      .into_iter()
+      .map(|pos| {
+        let param_type = self.u8(pos)?;
+        if param_type == TagParam::LIST_TYPE {
+          let count = self.u16(pos + 2)? as usize;
+          Ok(TagParam {
+            param_type,
+            list_item_indices: self.u16_list(pos + 4, count)?,
+            name: self.string(pos + 4 + count * 2)?,
+          })
+        } else {
+          Ok(TagParam {
+            param_type,
+            list_item_indices: Vec::new(),
+            name: self.string(pos + 1)?,
+          })
+        }
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Tgp2 {
+      section,
+      params,
+    })
+  }
+
+  fn read_tgl2(&self, section: Section) -> Result<Tgl2> {
+    let items = self.offsets(2)?
+      .into_iter()
+      .map(|pos| self.string(pos))
+      .collect::<Result<_>>()?;
+
+    Ok(Tgl2 {
+      section,
+      items,
+    })
+  }
+
+  fn read_syl3(&self, section: Section) -> Result<Syl3> {
+    let count = self.u32(0)? as usize;
+    let styles = (0..count)
+      .map(|i| {
+        let pos = 4 + i * 16;
+        Ok(Style {
+          region_width: self.u32(pos)?,
+          line_count: self.u32(pos + 4)?,
+          font_index: self.u32(pos + 8)?,
+          base_color_index: self.i32(pos + 12)?,
+        })
+      })
+      .collect::<Result<_>>()?;
+
+    Ok(Syl3 {
+      section,
+      styles,
+    })
+  }
+
+  fn read_cti1(&self, section: Section) -> Result<Cti1> {
+    let filenames = self.offsets(4)?
+      .into_iter()
+      .map(|pos| self.string(pos))
+      .collect::<Result<_>>()?;
+
+    Ok(Cti1 {
+      section,
+      filenames,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    Encoding,
+    msbp::builder::MsbpBuilder,
+    schema::{PARAM_PADDING, ParamValue, TagParams},
+  };
+
+  use std::io::Cursor;
+
+  #[test]
+  fn rejects_oversized_label_counts() {
+    let msbp = MsbpBuilder::new(Endianness::Little, 1)
+      .color("red", Color { r: 255, g: 0, b: 0, a: 255 })
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbp.write_to(&mut bytes).unwrap();
+
+    let clb1 = bytes.windows(4).position(|w| w == b"CLB1").unwrap();
+    bytes[clb1 + 20..clb1 + 24].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(Msbp::from_reader(Cursor::new(bytes)).is_err());
+  }
+
+  #[test]
+  fn derives_tag_schemas() {
+    let colors = ParamType::Enum { values: vec!["red".to_string(), "blue".to_string()] };
+    let msbp = MsbpBuilder::new(Endianness::Big, 1)
+      .tag_group(0, "system")
+      .tag_group(1, "ui")
+      .tag(0, "ruby", vec![ParamDef::new("text", ParamType::String)])
+      .tag(1, "color", vec![ParamDef::new("color", colors.clone())])
+      .tag(1, "size", vec![ParamDef::new("percent", ParamType::U16), ParamDef::new("shift", ParamType::I8)])
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbp.write_to(&mut bytes).unwrap();
+    let schema = Msbp::from_reader(Cursor::new(bytes)).unwrap().tag_schema();
+
+    assert_eq!(schema.tags().count(), 3);
+    assert_eq!(schema.get(0, 0).map(|t| t.name.as_str()), Some("ruby"));
+    assert_eq!(schema.get(1, 0), Some(&TagDef::new(1, 0, "color", vec![ParamDef::new("color", colors)])));
+    let size = schema.get_by_name("size").unwrap();
+    assert_eq!((size.group, size.tag_type), (1, 1));
+    assert_eq!(size.params.iter().map(|p| p.kind.name()).collect::<Vec<_>>(), vec!["u16", "i8"]);
+
+    let tag = schema.decode(1, 0, &[1, PARAM_PADDING], Encoding::Utf16, Endianness::Big);
+    assert_eq!(tag.name, Some("color"));
+    assert_eq!(tag.params, TagParams::Named(vec![("color".to_string(), ParamValue::Enum("blue".to_string()))]));
+    let tag = schema.decode(1, 1, &[0, 150, 0xfe, PARAM_PADDING], Encoding::Utf16, Endianness::Big);
+    assert_eq!(tag.params, TagParams::Named(vec![
+      ("percent".to_string(), ParamValue::U16(150)),
+      ("shift".to_string(), ParamValue::I8(-2)),
+    ]));
+  }
+}
@@ -0,0 +1,18 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Ali2 {
+  pub(crate) section: Section,
+  pub(crate) lists: Vec<Vec<String>>,
+}
+
+impl Ali2 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  /// The item names of every attribute list.
+  pub fn lists(&self) -> &[Vec<String>] {
+    &self.lists
+  }
+}
@@ -0,0 +1,58 @@
+use crate::section::Section;
+use super::ValueType;
+
+#[derive(Debug)]
+pub struct Ati2 {
+  pub(crate) section: Section,
+  pub(crate) attributes: Vec<AttributeInfo>,
+}
+
+impl Ati2 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn attributes(&self) -> &[AttributeInfo] {
+    &self.attributes
+  }
+}
+
+/// The definition of an attribute stored in ATR1 records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeInfo {
+  pub(crate) attr_type: u8,
+  pub(crate) _unknown_1: u8,
+  pub(crate) list_index: u16,
+  pub(crate) offset: u32,
+}
+
+impl AttributeInfo {
+  pub fn attr_type(&self) -> u8 {
+    self.attr_type
+  }
+
+  pub fn unknown_1(&self) -> u8 {
+    self._unknown_1
+  }
+
+  /// The index of this attribute's list in ALI2, if it is a list attribute.
+  pub fn list_index(&self) -> u16 {
+    self.list_index
+  }
+
+  /// The offset of this attribute in an ATR1 record.
+  pub fn offset(&self) -> u32 {
+    self.offset
+  }
+}
+
+/// An attribute definition resolved from ATI2, its label in ALB1 and its list in ALI2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDef {
+  pub name: String,
+  pub value_type: ValueType,
+  /// The offset of this attribute in an ATR1 record.
+  pub offset: u32,
+  /// The item names of a list attribute.
+  pub items: Vec<String>,
+}
@@ -0,0 +1,445 @@
+use crate::{
+  Encoding,
+  Header,
+  error::{Error, Result},
+  schema::{ParamDef, ParamType},
+  section::{Section, lbl1::{Group, Label}},
+  traits::Updates,
+};
+use super::{
+  Ali2,
+  Ati2,
+  Clr1,
+  Cti1,
+  LabelTable,
+  MSBP_MAGIC,
+  Msbp,
+  MsbpSectionTag,
+  Syl3,
+  Tag2,
+  Tgg2,
+  Tgl2,
+  Tgp2,
+  ValueType,
+  ati2::{AttributeDef, AttributeInfo},
+  clr1::Color,
+  label_table::LabelEntry,
+  syl3::Style,
+  tag2::TagInfo,
+  tgg2::TagGroup,
+  tgp2::TagParam,
+};
+
+use byteordered::Endianness;
+
+use std::collections::HashSet;
+
+/// Builds an MSBP project file from colour, attribute, tag and style definitions.
+pub struct MsbpBuilder {
+  endianness: Endianness,
+  group_count: u32,
+  colors: Vec<(String, Color)>,
+  attributes: Vec<AttributeDef>,
+  tag_groups: Vec<TagGroupDef>,
+  styles: Vec<(String, Style)>,
+  source_files: Vec<String>,
+}
+
+struct TagGroupDef {
+  id: u16,
+  // tags can be added to groups that were never defined, which build reports
+  name: Option<String>,
+  tags: Vec<(String, Vec<ParamDef>)>,
+}
+
+impl MsbpBuilder {
+  /// Creates a builder whose label tables (CLB1, ALB1 and SLB1) use `group_count` hash groups.
+  ///
+  /// A `group_count` of 0 makes [`build`](MsbpBuilder::build) fail.
+  pub fn new(endianness: Endianness, group_count: u32) -> Self {
+    MsbpBuilder {
+      endianness,
+      group_count,
+      colors: Vec::new(),
+      attributes: Vec::new(),
+      tag_groups: Vec::new(),
+      styles: Vec::new(),
+      source_files: Vec::new(),
+    }
+  }
+
+  pub fn color<S: Into<String>>(mut self, name: S, color: Color) -> Self {
+    self.colors.push((name.into(), color));
+
+    self
+  }
+
+  /// Adds an attribute stored at `offset` in each ATR1 record.
+  ///
+  /// List attributes should be added with [`list_attribute`](MsbpBuilder::list_attribute).
+  pub fn attribute<S: Into<String>>(mut self, name: S, value_type: ValueType, offset: u32) -> Self {
+    self.attributes.push(AttributeDef {
+      name: name.into(),
+      value_type,
+      offset,
+      items: Vec::new(),
+    });
+
+    self
+  }
+
+  /// Adds a list attribute stored at `offset` in each ATR1 record, as a u8 index into `items`.
+  pub fn list_attribute<S, I, T>(mut self, name: S, offset: u32, items: I) -> Self
+    where S: Into<String>,
+          I: IntoIterator<Item = T>,
+          T: Into<String>,
+  {
+    self.attributes.push(AttributeDef {
+      name: name.into(),
+      value_type: ValueType::List,
+      offset,
+      items: items.into_iter().map(Into::into).collect(),
+    });
+
+    self
+  }
+
+  /// Adds a tag group, which control tags refer to by `id`.
+  pub fn tag_group<S: Into<String>>(mut self, id: u16, name: S) -> Self {
+    match self.tag_groups.iter_mut().find(|g| g.id == id) {
+      Some(g) => g.name = Some(name.into()),
+      None => self.tag_groups.push(TagGroupDef {
+        id,
+        name: Some(name.into()),
+        tags: Vec::new(),
+      }),
+    }
+
+    self
+  }
+
+  /// Adds a tag to the group `group`. Tags are numbered in the order they are added to a group.
+  ///
+  /// Enum parameters become list parameters. Padding parameters cannot be stored in an MSBP and
+  /// make [`build`](MsbpBuilder::build) fail, as does a group that was never added.
+  pub fn tag<S: Into<String>>(mut self, group: u16, name: S, params: Vec<ParamDef>) -> Self {
+    let name = name.into();
+    match self.tag_groups.iter_mut().find(|g| g.id == group) {
+      Some(g) => g.tags.push((name, params)),
+      None => self.tag_groups.push(TagGroupDef {
+        id: group,
+        name: None,
+        tags: vec![(name, params)],
+      }),
+    }
+
+    self
+  }
+
+  pub fn style<S: Into<String>>(mut self, name: S, style: Style) -> Self {
+    self.styles.push((name.into(), style));
+
+    self
+  }
+
+  /// Adds the name of a source file the project was built from.
+  pub fn source_file<S: Into<String>>(mut self, name: S) -> Self {
+    self.source_files.push(name.into());
+
+    self
+  }
+
+  /// Builds the file.
+  ///
+  /// Colour, attribute and style names must each be unique, since they are looked up by name.
+  pub fn build(self) -> Result<Msbp> {
+    if self.group_count == 0 {
+      return Err(Error::InvalidGroupCount(self.group_count));
+    }
+    let mut section_order = Vec::with_capacity(12);
+
+    let (clr1, clb1) = if self.colors.is_empty() {
+      (None, None)
+    } else {
+      section_order.extend(&[MsbpSectionTag::Clr1, MsbpSectionTag::Clb1]);
+      let clr1 = Clr1 {
+        section: Section::new(*b"CLR1", 0),
+        colors: self.colors.iter().map(|&(_, color)| color).collect(),
+      };
+      let clb1 = self.label_table(*b"CLB1", self.colors.iter().map(|(name, _)| name.as_str()))?;
+      (Some(clr1), Some(clb1))
+    };
+
+    let (ati2, alb1, ali2) = if self.attributes.is_empty() {
+      (None, None, None)
+    } else {
+      section_order.extend(&[MsbpSectionTag::Ati2, MsbpSectionTag::Alb1, MsbpSectionTag::Ali2]);
+      let mut lists = Vec::new();
+      let attributes = self.attributes
+        .iter()
+        .map(|attr| {
+          let list_index = if attr.value_type == ValueType::List {
+            lists.push(attr.items.clone());
+            lists.len() as u16 - 1
+          } else {
+            0
+          };
+          AttributeInfo {
+            attr_type: attr.value_type as u8,
+            _unknown_1: 0,
+            list_index,
+            offset: attr.offset,
+          }
+        })
+        .collect();
+      let ati2 = Ati2 {
+        section: Section::new(*b"ATI2", 0),
+        attributes,
+      };
+      let alb1 = self.label_table(*b"ALB1", self.attributes.iter().map(|attr| attr.name.as_str()))?;
+      let ali2 = Ali2 {
+        section: Section::new(*b"ALI2", 0),
+        lists,
+      };
+      (Some(ati2), Some(alb1), Some(ali2))
+    };
+
+    let (tgg2, tag2, tgp2, tgl2) = if self.tag_groups.is_empty() {
+      (None, None, None, None)
+    } else {
+      section_order.extend(&[MsbpSectionTag::Tgg2, MsbpSectionTag::Tag2, MsbpSectionTag::Tgp2, MsbpSectionTag::Tgl2]);
+      let (tgg2, tag2, tgp2, tgl2) = self.tag_sections()?;
+      (Some(tgg2), Some(tag2), Some(tgp2), Some(tgl2))
+    };
+
+    let (syl3, slb1) = if self.styles.is_empty() {
+      (None, None)
+    } else {
+      section_order.extend(&[MsbpSectionTag::Syl3, MsbpSectionTag::Slb1]);
+      let syl3 = Syl3 {
+        section: Section::new(*b"SYL3", 0),
+        styles: self.styles.iter().map(|&(_, style)| style).collect(),
+      };
+      let slb1 = self.label_table(*b"SLB1", self.styles.iter().map(|(name, _)| name.as_str()))?;
+      (Some(syl3), Some(slb1))
+    };
+
+    let cti1 = if self.source_files.is_empty() {
+      None
+    } else {
+      section_order.push(MsbpSectionTag::Cti1);
+      Some(Cti1 {
+        section: Section::new(*b"CTI1", 0),
+        filenames: self.source_files.clone(),
+      })
+    };
+
+    let mut msbp = Msbp {
+      header: Header {
+        magic: MSBP_MAGIC,
+        endianness: self.endianness,
+        _unknown_1: 0,
+        encoding: Encoding::Utf8,
+        version: 3,
+        section_count: section_order.len() as u16,
+        _unknown_3: 0,
+        file_size: 0,
+        padding: [0; 10],
+      },
+      section_order,
+      clr1,
+      clb1,
+      ati2,
+      alb1,
+      ali2,
+      tgg2,
+      tag2,
+      tgp2,
+      tgl2,
+      syl3,
+      slb1,
+      cti1,
+    };
+    msbp.update();
+
+    Ok(msbp)
+  }
+
+  /// Builds a label table naming entries in order, failing if a name is used twice.
+  fn label_table<'a, I>(&self, magic: [u8; 4], names: I) -> Result<LabelTable>
+    where I: IntoIterator<Item = &'a str>,
+  {
+    let mut seen = HashSet::new();
+    let labels = names.into_iter()
+      .enumerate()
+      .map(|(index, name)| {
+        if !seen.insert(name) {
+          return Err(Error::DuplicateLabel(name.to_string()));
+        }
+        Ok(LabelEntry {
+          name: name.to_string(),
+          index: index as u32,
+          checksum: Label::hash(name, self.group_count),
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let groups = (0..self.group_count)
+      .map(|_| Group {
+        label_count: 0,
+        offset: 0,
+      })
+      .collect();
+    Ok(LabelTable {
+      section: Section::new(magic, 0),
+      group_count: self.group_count,
+      groups,
+      labels,
+    })
+  }
+
+  fn tag_sections(&self) -> Result<(Tgg2, Tag2, Tgp2, Tgl2)> {
+    let mut groups = Vec::with_capacity(self.tag_groups.len());
+    let mut tags = Vec::new();
+    let mut params = Vec::new();
+    let mut items = Vec::new();
+
+    for group in &self.tag_groups {
+      let name = group.name.clone().ok_or(Error::UnknownTagGroup(group.id))?;
+
+      let mut tag_indices = Vec::with_capacity(group.tags.len());
+      for (tag_name, tag_params) in &group.tags {
+        let mut param_indices = Vec::with_capacity(tag_params.len());
+        for param in tag_params {
+          let (param_type, list_item_indices) = match param.kind {
+            ParamType::U8 => (ValueType::U8, Vec::new()),
+            ParamType::U16 => (ValueType::U16, Vec::new()),
+            ParamType::U32 => (ValueType::U32, Vec::new()),
+            ParamType::I8 => (ValueType::I8, Vec::new()),
+            ParamType::I16 => (ValueType::I16, Vec::new()),
+            ParamType::I32 => (ValueType::I32, Vec::new()),
+            ParamType::F32 => (ValueType::F32, Vec::new()),
+            ParamType::String => (ValueType::String, Vec::new()),
+            ParamType::Enum { ref values } => {
+              let indices = (items.len()..items.len() + values.len()).map(|i| i as u16).collect();
+              items.extend(values.iter().cloned());
+              (ValueType::List, indices)
+            },
+            ParamType::Padding => return Err(Error::InvalidTagParam {
+              tag: tag_name.clone(),
+              param: param.name.clone(),
+              reason: "padding parameters cannot be stored in an MSBP".to_string(),
+            }),
+          };
+          param_indices.push(params.len() as u16);
+          params.push(TagParam {
+            param_type: param_type as u8,
+            list_item_indices,
+            name: param.name.clone(),
+          });
+        }
+        tag_indices.push(tags.len() as u16);
+        tags.push(TagInfo {
+          param_indices,
+          name: tag_name.clone(),
+        });
+      }
+
+      groups.push(TagGroup {
+        id: group.id,
+        tag_indices,
+        name,
+      });
+    }
+
+    Ok((
+      Tgg2 { section: Section::new(*b"TGG2", 0), groups },
+      Tag2 { section: Section::new(*b"TAG2", 0), tags },
+      Tgp2 { section: Section::new(*b"TGP2", 0), params },
+      Tgl2 { section: Section::new(*b"TGL2", 0), items },
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::io::Cursor;
+
+  fn source(endianness: Endianness) -> Vec<u8> {
+    let enum_param = ParamType::Enum { values: vec!["red".to_string(), "blue".to_string()] };
+    let msbp = MsbpBuilder::new(endianness, 7)
+      .color("red", Color { r: 255, g: 0, b: 0, a: 255 })
+      .color("blue", Color { r: 0, g: 0, b: 255, a: 128 })
+      .attribute("speaker", ValueType::String, 0)
+      .list_attribute("mood", 4, vec!["calm", "angry"])
+      .tag_group(0, "System")
+      .tag(0, "Ruby", vec![ParamDef::new("rt", ParamType::String)])
+      .tag(0, "Color", vec![ParamDef::new("color", enum_param), ParamDef::new("size", ParamType::U16)])
+      .style("default", Style { region_width: 400, line_count: 3, font_index: 0, base_color_index: -1 })
+      .source_file("common.mstxt")
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbp.write_to(&mut bytes).unwrap();
+    bytes
+  }
+
+  // builds a file from everything that can be read back from `msbp`
+  fn rebuild(msbp: &Msbp) -> Msbp {
+    let clb1 = msbp.clb1().unwrap();
+    let mut builder = MsbpBuilder::new(msbp.header().endianness(), clb1.group_count());
+    for (i, &color) in msbp.clr1().unwrap().colors().iter().enumerate() {
+      builder = builder.color(clb1.name_of(i as u32).unwrap(), color);
+    }
+    for def in msbp.attribute_defs() {
+      builder = match def.value_type {
+        ValueType::List => builder.list_attribute(def.name, def.offset, def.items),
+        value_type => builder.attribute(def.name, value_type, def.offset),
+      };
+    }
+    for group in msbp.tgg2().unwrap().groups() {
+      builder = builder.tag_group(group.id(), group.name());
+    }
+    for tag in msbp.tag_schema().tags() {
+      builder = builder.tag(tag.group, tag.name.clone(), tag.params.clone());
+    }
+    let slb1 = msbp.slb1().unwrap();
+    for (i, &style) in msbp.syl3().unwrap().styles().iter().enumerate() {
+      builder = builder.style(slb1.name_of(i as u32).unwrap(), style);
+    }
+    for name in msbp.cti1().unwrap().filenames() {
+      builder = builder.source_file(name.clone());
+    }
+    builder.build().unwrap()
+  }
+
+  #[test]
+  fn rebuilds_read_files() {
+    for &endianness in &[Endianness::Little, Endianness::Big] {
+      let bytes = source(endianness);
+      let msbp = Msbp::from_reader(Cursor::new(&bytes)).unwrap();
+      assert_eq!(msbp.style("default").map(|s| s.base_color_index), Some(-1));
+
+      let mut written = Vec::new();
+      rebuild(&msbp).write_to(&mut written).unwrap();
+      assert_eq!(written, bytes, "{:?}", endianness);
+    }
+  }
+
+  #[test]
+  fn rejects_duplicate_names() {
+    let red = Color { r: 255, g: 0, b: 0, a: 255 };
+    let style = Style { region_width: 0, line_count: 1, font_index: 0, base_color_index: 0 };
+    let builders = vec![
+      MsbpBuilder::new(Endianness::Little, 1).color("red", red).color("red", red),
+      MsbpBuilder::new(Endianness::Little, 1)
+        .attribute("red", ValueType::U8, 0)
+        .list_attribute("red", 1, vec!["a"]),
+      MsbpBuilder::new(Endianness::Little, 1).style("red", style).style("red", style),
+    ];
+    for builder in builders {
+      assert!(matches!(builder.build(), Err(Error::DuplicateLabel(ref name)) if name == "red"));
+    }
+  }
+}
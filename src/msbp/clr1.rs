@@ -0,0 +1,25 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Clr1 {
+  pub(crate) section: Section,
+  pub(crate) colors: Vec<Color>,
+}
+
+impl Clr1 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn colors(&self) -> &[Color] {
+    &self.colors
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+  pub a: u8,
+}
@@ -0,0 +1,18 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Cti1 {
+  pub(crate) section: Section,
+  pub(crate) filenames: Vec<String>,
+}
+
+impl Cti1 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  /// The source files the project was built from.
+  pub fn filenames(&self) -> &[String] {
+    &self.filenames
+  }
+}
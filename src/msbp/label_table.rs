@@ -0,0 +1,83 @@
+use crate::{
+  section::{Section, lbl1::Group},
+  traits::CalculatesSize,
+};
+
+/// A hash table of labels naming the entries of another section, laid out like LBL1.
+///
+/// This is used by CLB1, ALB1 and SLB1.
+#[derive(Debug)]
+pub struct LabelTable {
+  pub(crate) section: Section,
+  pub(crate) group_count: u32,
+  pub(crate) groups: Vec<Group>,
+  pub(crate) labels: Vec<LabelEntry>,
+}
+
+impl LabelTable {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn group_count(&self) -> u32 {
+    self.group_count
+  }
+
+  pub fn groups(&self) -> &[Group] {
+    &self.groups
+  }
+
+  pub fn labels(&self) -> &[LabelEntry] {
+    &self.labels
+  }
+
+  /// Gets the index labelled with `name`.
+  pub fn index_of(&self, name: &str) -> Option<u32> {
+    self.labels.iter().find(|l| l.name == name).map(|l| l.index)
+  }
+
+  /// Recalculates the label count and offset of every group from the labels.
+  pub(crate) fn update_groups(&mut self) {
+    let mut offset = std::mem::size_of_val(&self.group_count) + self.groups.iter().map(CalculatesSize::calc_size).sum::<usize>();
+    for (i, group) in self.groups.iter_mut().enumerate() {
+      let labels = self.labels.iter().filter(|l| l.checksum == i as u32);
+      group.label_count = labels.clone().count() as u32;
+      group.offset = offset as u32;
+      offset += labels.map(CalculatesSize::calc_size).sum::<usize>();
+    }
+  }
+
+  /// Gets the label of `index`.
+  pub fn name_of(&self, index: u32) -> Option<&str> {
+    self.labels.iter().find(|l| l.index == index).map(|l| l.name.as_str())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelEntry {
+  pub(crate) name: String,
+  pub(crate) index: u32,
+  pub(crate) checksum: u32,
+}
+
+impl LabelEntry {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn index(&self) -> u32 {
+    self.index
+  }
+
+  pub fn checksum(&self) -> u32 {
+    self.checksum
+  }
+}
+
+impl CalculatesSize for LabelEntry {
+  fn calc_size(&self) -> usize {
+    std::mem::size_of::<u8>() // name length
+      + self.name.len()
+      + std::mem::size_of_val(&self.index)
+  }
+}
@@ -0,0 +1,25 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Syl3 {
+  pub(crate) section: Section,
+  pub(crate) styles: Vec<Style>,
+}
+
+impl Syl3 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn styles(&self) -> &[Style] {
+    &self.styles
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+  pub region_width: u32,
+  pub line_count: u32,
+  pub font_index: u32,
+  pub base_color_index: i32,
+}
@@ -0,0 +1,34 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Tag2 {
+  pub(crate) section: Section,
+  pub(crate) tags: Vec<TagInfo>,
+}
+
+impl Tag2 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn tags(&self) -> &[TagInfo] {
+    &self.tags
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagInfo {
+  pub(crate) param_indices: Vec<u16>,
+  pub(crate) name: String,
+}
+
+impl TagInfo {
+  /// The indices of this tag's parameters in TGP2.
+  pub fn param_indices(&self) -> &[u16] {
+    &self.param_indices
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
@@ -0,0 +1,40 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Tgg2 {
+  pub(crate) section: Section,
+  pub(crate) groups: Vec<TagGroup>,
+}
+
+impl Tgg2 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn groups(&self) -> &[TagGroup] {
+    &self.groups
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagGroup {
+  pub(crate) id: u16,
+  pub(crate) tag_indices: Vec<u16>,
+  pub(crate) name: String,
+}
+
+impl TagGroup {
+  /// The group number used by control tags in this group.
+  pub fn id(&self) -> u16 {
+    self.id
+  }
+
+  /// The indices of this group's tags in TAG2, in tag type order.
+  pub fn tag_indices(&self) -> &[u16] {
+    &self.tag_indices
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
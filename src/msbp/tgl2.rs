@@ -0,0 +1,18 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Tgl2 {
+  pub(crate) section: Section,
+  pub(crate) items: Vec<String>,
+}
+
+impl Tgl2 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  /// The names of all list items used by tag parameters.
+  pub fn items(&self) -> &[String] {
+    &self.items
+  }
+}
@@ -0,0 +1,42 @@
+use crate::section::Section;
+
+#[derive(Debug)]
+pub struct Tgp2 {
+  pub(crate) section: Section,
+  pub(crate) params: Vec<TagParam>,
+}
+
+impl Tgp2 {
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn params(&self) -> &[TagParam] {
+    &self.params
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagParam {
+  pub(crate) param_type: u8,
+  pub(crate) list_item_indices: Vec<u16>,
+  pub(crate) name: String,
+}
+
+impl TagParam {
+  /// The type of a list parameter.
+  pub const LIST_TYPE: u8 = 9;
+
+  pub fn param_type(&self) -> u8 {
+    self.param_type
+  }
+
+  /// The indices of this parameter's items in TGL2, if it is a list parameter.
+  pub fn list_item_indices(&self) -> &[u16] {
+    &self.list_item_indices
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+}
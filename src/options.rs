@@ -0,0 +1,54 @@
+//! Options for reading MSBT files.
+
+use thiserror::Error;
+
+/// Controls how [`Msbt::from_reader_with_options`](crate::Msbt::from_reader_with_options) handles
+/// malformed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOptions {
+  /// Whether to fail on the first problem. When `false`, the reader recovers where it can and
+  /// reports each recovery as a [`ReadWarning`].
+  pub strict: bool,
+}
+
+impl Default for ReadOptions {
+  fn default() -> Self {
+    ReadOptions {
+      strict: true,
+    }
+  }
+}
+
+/// A problem the reader recovered from in lenient mode.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ReadWarning {
+  #[error("section {magic:?} at {offset:#x} claims {declared} bytes, but only {available} remain")]
+  TruncatedSection {
+    magic: [u8; 4],
+    offset: u64,
+    declared: u32,
+    available: u32,
+  },
+  #[error("section {magic:?} at {offset:#x} could not be parsed and was kept as raw bytes: {reason}")]
+  UnparsedSection {
+    magic: [u8; 4],
+    offset: u64,
+    reason: String,
+  },
+  #[error("label at {offset:#x} is not valid utf-8 and was read as {name:?}")]
+  LossyLabelName {
+    offset: u64,
+    name: String,
+  },
+  #[error("offset {offset:#x} of TXT2 string {index} was clamped to {clamped:#x}")]
+  ClampedStringOffset {
+    index: u32,
+    offset: u32,
+    clamped: u32,
+  },
+  #[error("ignored {len} bytes of trailing data at {offset:#x}")]
+  TrailingData {
+    offset: u64,
+    len: u64,
+  },
+}
@@ -0,0 +1,371 @@
+use crate::{
+  Encoding,
+  error::{Error, Result},
+  message::{self, Segment},
+};
+
+use byteordered::{Endian, Endianness};
+
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+/// The byte written for padding parameters.
+pub const PARAM_PADDING: u8 = 0xCD;
+
+/// A registry of known control tags, mapping group and type numbers to names and parameter
+/// layouts.
+///
+/// With the `serde_support` feature, a schema can be loaded from any serde format. The schema is
+/// represented as a list of tags:
+///
+/// ```toml
+/// [[tags]]
+/// group = 0
+/// type = 3
+/// name = "color"
+/// params = [
+///   { name = "r", type = "u8" },
+///   { name = "g", type = "u8" },
+///   { name = "b", type = "u8" },
+///   { name = "a", type = "u8" },
+/// ]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(from = "SchemaFile", into = "SchemaFile"))]
+pub struct TagSchema {
+  tags: BTreeMap<(u16, u16), TagDef>,
+}
+
+#[cfg(feature = "serde_support")]
+#[derive(Deserialize, Serialize)]
+struct SchemaFile {
+  tags: Vec<TagDef>,
+}
+
+#[cfg(feature = "serde_support")]
+impl From<SchemaFile> for TagSchema {
+  fn from(file: SchemaFile) -> Self {
+    file.tags.into_iter().collect()
+  }
+}
+
+#[cfg(feature = "serde_support")]
+impl From<TagSchema> for SchemaFile {
+  fn from(schema: TagSchema) -> Self {
+    SchemaFile {
+      tags: schema.tags.into_values().collect(),
+    }
+  }
+}
+
+impl TagSchema {
+  pub fn new() -> Self {
+    TagSchema::default()
+  }
+
+  /// Adds a tag definition, returning the definition it replaced, if any.
+  pub fn insert(&mut self, def: TagDef) -> Option<TagDef> {
+    self.tags.insert((def.group, def.tag_type), def)
+  }
+
+  pub fn get(&self, group: u16, tag_type: u16) -> Option<&TagDef> {
+    self.tags.get(&(group, tag_type))
+  }
+
+  pub fn get_by_name(&self, name: &str) -> Option<&TagDef> {
+    self.tags.values().find(|def| def.name == name)
+  }
+
+  pub fn tags(&self) -> impl Iterator<Item = &TagDef> {
+    self.tags.values()
+  }
+
+  /// Decodes a control tag using this schema.
+  ///
+  /// Tags that are not in the schema, or whose parameters do not match their definition, keep
+  /// their parameters as opaque bytes.
+  pub fn decode<'a>(&'a self, group: u16, tag_type: u16, params: &[u8], encoding: Encoding, endianness: Endianness) -> DecodedTag<'a> {
+    let def = self.get(group, tag_type);
+    let params = def
+      .and_then(|def| def.decode_params(params, encoding, endianness))
+      .map(TagParams::Named)
+      .unwrap_or_else(|| TagParams::Opaque(params.to_vec()));
+    DecodedTag {
+      group,
+      tag_type,
+      name: def.map(|def| def.name.as_str()),
+      params,
+    }
+  }
+
+  /// Decodes a segment if it is a control tag.
+  pub fn decode_segment<'a>(&'a self, segment: &Segment, encoding: Encoding, endianness: Endianness) -> Option<DecodedTag<'a>> {
+    match *segment {
+      Segment::Tag { group, tag_type, ref params } => Some(self.decode(group, tag_type, params, encoding, endianness)),
+      _ => None,
+    }
+  }
+}
+
+impl std::iter::FromIterator<TagDef> for TagSchema {
+  fn from_iter<I: IntoIterator<Item = TagDef>>(iter: I) -> Self {
+    let mut schema = TagSchema::new();
+    for def in iter {
+      schema.insert(def);
+    }
+    schema
+  }
+}
+
+/// The definition of a single control tag.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+pub struct TagDef {
+  pub group: u16,
+  #[cfg_attr(feature = "serde_support", serde(rename = "type"))]
+  pub tag_type: u16,
+  pub name: String,
+  #[cfg_attr(feature = "serde_support", serde(default))]
+  pub params: Vec<ParamDef>,
+}
+
+impl TagDef {
+  pub fn new<S: Into<String>>(group: u16, tag_type: u16, name: S, params: Vec<ParamDef>) -> Self {
+    TagDef {
+      group,
+      tag_type,
+      name: name.into(),
+      params,
+    }
+  }
+
+  /// Decodes raw parameter bytes into named values.
+  ///
+  /// Returns `None` if the bytes do not match this definition exactly.
+  pub fn decode_params(&self, raw: &[u8], encoding: Encoding, endianness: Endianness) -> Option<Vec<(String, ParamValue)>> {
+    let mut pos = 0;
+    let mut values = Vec::with_capacity(self.params.len());
+
+    for param in &self.params {
+      let value = match param.kind {
+        ParamType::U8 => ParamValue::U8(take(raw, &mut pos, 1)?[0]),
+        ParamType::U16 => ParamValue::U16(endianness.read_u16(take(raw, &mut pos, 2)?).ok()?),
+        ParamType::U32 => ParamValue::U32(endianness.read_u32(take(raw, &mut pos, 4)?).ok()?),
+        ParamType::I8 => ParamValue::I8(take(raw, &mut pos, 1)?[0] as i8),
+        ParamType::I16 => ParamValue::I16(endianness.read_i16(take(raw, &mut pos, 2)?).ok()?),
+        ParamType::I32 => ParamValue::I32(endianness.read_i32(take(raw, &mut pos, 4)?).ok()?),
+        ParamType::F32 => {
+          let f = endianness.read_f32(take(raw, &mut pos, 4)?).ok()?;
+          if f.is_nan() {
+            return None;
+          }
+          ParamValue::F32(f)
+        },
+        ParamType::String => {
+          let len = endianness.read_u16(take(raw, &mut pos, 2)?).ok()? as usize;
+          let bytes = take(raw, &mut pos, len)?;
+          ParamValue::String(message::decode_string(bytes, encoding, endianness).ok()?)
+        },
+        ParamType::Enum { ref values } => {
+          let index = take(raw, &mut pos, 1)?[0] as usize;
+          ParamValue::Enum(values.get(index)?.clone())
+        },
+        ParamType::Padding => {
+          take(raw, &mut pos, 1)?;
+          continue;
+        },
+      };
+      values.push((param.name.clone(), value));
+    }
+
+    // only accept decodings that encode back to the same bytes, which also rejects leftover bytes
+    match self.encode_params(&values, encoding, endianness) {
+      Ok(ref encoded) if encoded.as_slice() == raw => Some(values),
+      _ => None,
+    }
+  }
+
+  /// Encodes named values into raw parameter bytes.
+  ///
+  /// Every non-padding parameter must be given a value of its type. In UTF-16 and UTF-32 messages,
+  /// a padding byte is added if the parameters would otherwise have an odd length.
+  pub fn encode_params(&self, values: &[(String, ParamValue)], encoding: Encoding, endianness: Endianness) -> Result<Vec<u8>> {
+    if let Some((name, _)) = values.iter().find(|(name, _)| !self.params.iter().any(|p| &p.name == name && p.kind != ParamType::Padding)) {
+      return Err(self.param_error(name, "unknown parameter"));
+    }
+
+    let mut buf = Vec::new();
+    for param in &self.params {
+      if param.kind == ParamType::Padding {
+        buf.push(PARAM_PADDING);
+        continue;
+      }
+
+      let value = values.iter()
+        .find(|(name, _)| name == &param.name)
+        .map(|(_, value)| value)
+        .ok_or_else(|| self.param_error(&param.name, "missing parameter"))?;
+
+      match (&param.kind, value) {
+        (ParamType::U8, ParamValue::U8(x)) => buf.push(*x),
+        (ParamType::U16, ParamValue::U16(x)) => endianness.write_u16(&mut buf, *x).map_err(Error::Io)?,
+        (ParamType::U32, ParamValue::U32(x)) => endianness.write_u32(&mut buf, *x).map_err(Error::Io)?,
+        (ParamType::I8, ParamValue::I8(x)) => buf.push(*x as u8),
+        (ParamType::I16, ParamValue::I16(x)) => endianness.write_i16(&mut buf, *x).map_err(Error::Io)?,
+        (ParamType::I32, ParamValue::I32(x)) => endianness.write_i32(&mut buf, *x).map_err(Error::Io)?,
+        (ParamType::F32, ParamValue::F32(x)) => endianness.write_f32(&mut buf, *x).map_err(Error::Io)?,
+        (ParamType::String, ParamValue::String(s)) => {
+          let bytes = message::encode_string(s, encoding, endianness);
+          if bytes.len() > u16::MAX as usize {
+            return Err(self.param_error(&param.name, "string too long"));
+          }
+          endianness.write_u16(&mut buf, bytes.len() as u16).map_err(Error::Io)?;
+          buf.extend(bytes);
+        },
+        (ParamType::Enum { values }, ParamValue::Enum(item)) => {
+          let index = values.iter()
+            .position(|v| v == item)
+            .ok_or_else(|| self.param_error(&param.name, format!("unknown item {:?}", item)))?;
+          buf.push(index as u8);
+        },
+        _ => return Err(self.param_error(&param.name, format!("expected a value of type {}", param.kind.name()))),
+      }
+    }
+
+    // UTF-16 and UTF-32 messages keep parameters at an even length
+    if encoding != Encoding::Utf8 && buf.len() % 2 == 1 {
+      buf.push(PARAM_PADDING);
+    }
+
+    Ok(buf)
+  }
+
+  fn param_error<R: Into<String>>(&self, param: &str, reason: R) -> Error {
+    Error::InvalidTagParam {
+      tag: self.name.clone(),
+      param: param.to_string(),
+      reason: reason.into(),
+    }
+  }
+}
+
+fn take<'a>(raw: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+  let bytes = raw.get(*pos..*pos + len)?;
+  *pos += len;
+  Some(bytes)
+}
+
+/// The definition of a single control tag parameter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+pub struct ParamDef {
+  pub name: String,
+  #[cfg_attr(feature = "serde_support", serde(flatten))]
+  pub kind: ParamType,
+}
+
+impl ParamDef {
+  pub fn new<S: Into<String>>(name: S, kind: ParamType) -> Self {
+    ParamDef {
+      name: name.into(),
+      kind,
+    }
+  }
+}
+
+/// The layout of a control tag parameter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ParamType {
+  U8,
+  U16,
+  U32,
+  I8,
+  I16,
+  I32,
+  F32,
+  /// A string prefixed by its u16 length in bytes, in the message's encoding.
+  String,
+  /// A u8 index into a list of named items.
+  Enum { values: Vec<String> },
+  /// A single padding byte.
+  Padding,
+}
+
+impl ParamType {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      ParamType::U8 => "u8",
+      ParamType::U16 => "u16",
+      ParamType::U32 => "u32",
+      ParamType::I8 => "i8",
+      ParamType::I16 => "i16",
+      ParamType::I32 => "i32",
+      ParamType::F32 => "f32",
+      ParamType::String => "string",
+      ParamType::Enum { .. } => "enum",
+      ParamType::Padding => "padding",
+    }
+  }
+}
+
+/// A decoded control tag parameter value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+  U8(u8),
+  U16(u16),
+  U32(u32),
+  I8(i8),
+  I16(i16),
+  I32(i32),
+  F32(f32),
+  String(String),
+  Enum(String),
+}
+
+/// A control tag decoded with a [`TagSchema`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedTag<'a> {
+  pub group: u16,
+  pub tag_type: u16,
+  /// The name of the tag, if it is in the schema.
+  pub name: Option<&'a str>,
+  pub params: TagParams,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagParams {
+  Named(Vec<(String, ParamValue)>),
+  Opaque(Vec<u8>),
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn schema() -> TagSchema {
+    vec![TagDef::new(0, 1, "ruby", vec![ParamDef::new("text", ParamType::String)])].into_iter().collect()
+  }
+
+  #[test]
+  fn decodes_string_params() {
+    let schema = schema();
+    let tag = schema.decode(0, 1, &[4, 0, 65, 0, 66, 0], Encoding::Utf16, Endianness::Little);
+    assert_eq!(tag.name, Some("ruby"));
+    assert_eq!(tag.params, TagParams::Named(vec![("text".to_string(), ParamValue::String("AB".to_string()))]));
+  }
+
+  #[test]
+  fn keeps_malformed_string_params_opaque() {
+    let schema = schema();
+    for raw in &[&[1, 0, 65][..], &[4, 0, 65, 0], &[2, 0, 0, 0xd8]] {
+      let tag = schema.decode(0, 1, raw, Encoding::Utf16, Endianness::Little);
+      assert_eq!(tag.name, Some("ruby"));
+      assert_eq!(tag.params, TagParams::Opaque(raw.to_vec()));
+    }
+  }
+}
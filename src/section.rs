@@ -4,6 +4,16 @@ pub mod lbl1;
 pub mod nli1;
 pub mod tsy1;
 pub mod txt2;
+pub mod unknown;
+
+use crate::error::{Error, Result};
+
+use byteordered::{Endian, Endianness};
+
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+use std::io::Read;
 
 pub use self::{
   ato1::Ato1,
@@ -12,11 +22,15 @@ pub use self::{
   nli1::Nli1,
   tsy1::Tsy1,
   txt2::Txt2,
+  unknown::UnknownSection,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Section {
   pub magic: [u8; 4],
+  /// Not serialized, since it is recalculated from the section's contents.
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub size: u32,
   pub padding: [u8; 8],
 }
@@ -29,6 +43,21 @@ impl Section {
       padding: [0; 8],
     }
   }
+
+  pub(crate) fn from_reader<R: Read>(mut reader: R, endianness: Endianness) -> Result<Self> {
+    let mut magic = [0; 4];
+    let mut padding = [0; 8];
+
+    reader.read_exact(&mut magic).map_err(Error::Io)?;
+    let size = endianness.read_u32(&mut reader).map_err(Error::Io)?;
+    reader.read_exact(&mut padding).map_err(Error::Io)?;
+
+    Ok(Section {
+      magic,
+      size,
+      padding,
+    })
+  }
 }
 
 impl crate::traits::CalculatesSize for Section {
@@ -1,43 +1,89 @@
 use crate::{
-  Msbt,
-  traits::CalculatesSize,
+  error::{Error, Result},
+  traits::{CalculatesSize, Updates},
 };
 use super::Section;
 
-use std::ptr::NonNull;
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug)]
+/// A table of u32 entries, commonly all 0xFFFFFFFF.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Ato1 {
-  pub(crate) msbt: NonNull<Msbt>,
   pub(crate) section: Section,
-  pub(crate) _unknown: Vec<u8>, // large collection of 0xFF
+  pub(crate) entries: Vec<u32>,
 }
 
 impl Ato1 {
-  pub fn new_unlinked<V: Into<Vec<u8>>>(unknown_bytes: V) -> Self {
-    let bytes = unknown_bytes.into();
-    Ato1 {
-      msbt: NonNull::dangling(),
-      section: Section::new(*b"ATO1", bytes.len() as u32),
-      _unknown: bytes,
+  pub fn new_unlinked<V: Into<Vec<u32>>>(entries: V) -> Self {
+    let mut ato1 = Ato1 {
+      section: Section::new(*b"ATO1", 0),
+      entries: entries.into(),
+    };
+    ato1.update();
+    ato1
+  }
+
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn entries(&self) -> &[u32] {
+    &self.entries
+  }
+
+  pub fn entry(&self, index: usize) -> Option<u32> {
+    self.entries.get(index).copied()
+  }
+
+  pub fn set_entry(&mut self, index: usize, entry: u32) -> Result<()> {
+    match self.entries.get_mut(index) {
+      Some(e) => *e = entry,
+      None => return Err(Error::NoSuchEntry(index)),
     }
+    Ok(())
   }
 
-  pub fn msbt(&self) -> &Msbt {
-    unsafe { self.msbt.as_ref() }
+  pub fn set_entries<V: Into<Vec<u32>>>(&mut self, entries: V) {
+    self.entries = entries.into();
+    self.update();
   }
 
-  pub fn section(&self) -> &Section {
-    &self.section
+  pub fn push(&mut self, entry: u32) {
+    self.entries.push(entry);
+    self.update();
+  }
+
+  /// Inserts an entry at `index`, shifting later entries up.
+  pub fn insert(&mut self, index: usize, entry: u32) -> Result<()> {
+    if index > self.entries.len() {
+      return Err(Error::NoSuchEntry(index));
+    }
+    self.entries.insert(index, entry);
+    self.update();
+    Ok(())
   }
 
-  pub fn unknown_bytes(&self) -> &[u8] {
-    &self._unknown
+  /// Removes and returns the entry at `index`, shifting later entries down.
+  pub fn remove(&mut self, index: usize) -> Result<u32> {
+    if index >= self.entries.len() {
+      return Err(Error::NoSuchEntry(index));
+    }
+    let entry = self.entries.remove(index);
+    self.update();
+    Ok(entry)
+  }
+}
+
+impl Updates for Ato1 {
+  fn update(&mut self) {
+    self.section.size = (self.entries.len() * std::mem::size_of::<u32>()) as u32;
   }
 }
 
 impl CalculatesSize for Ato1 {
   fn calc_size(&self) -> usize {
-    self.section.calc_size() + self._unknown.len()
+    self.section.calc_size() + self.entries.len() * std::mem::size_of::<u32>()
   }
 }
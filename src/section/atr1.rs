@@ -1,54 +1,481 @@
 use crate::{
-  Encoding,
+  Header,
   Msbt,
+  error::{Error, Result},
+  message,
+  msbp::{Msbp, ValueType, ati2::AttributeDef},
   traits::{CalculatesSize, Updates},
 };
 use super::Section;
 
-use std::ptr::NonNull;
+use byteordered::Endian;
 
-#[derive(Debug)]
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Attributes for each message, stored as fixed-size binary records.
+///
+/// Records may refer to strings stored in a string table after the records, by their offset from
+/// the start of the section's data.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Atr1 {
-  pub(crate) msbt: NonNull<Msbt>,
   pub(crate) section: Section,
-  pub(crate) string_count: u32,
-  pub(crate) _unknown_1: u32,
-  pub(crate) strings: Vec<String>,
+  pub(crate) entry_count: u32,
+  pub(crate) entry_size: u32,
+  /// The records, one after another. Records may be empty, so they are not stored separately.
+  pub(crate) records: Vec<u8>,
+  pub(crate) string_table: Vec<u8>,
 }
 
 impl Atr1 {
-  pub fn new_unlinked<I, S>(string_count: u32, _unknown_1: u32, strings: I) -> Self
-    where I: IntoIterator<Item = S>,
-          S: Into<String>,
+  /// Creates an Atr1 from records of `entry_size` bytes each and the string table following them.
+  pub fn new_unlinked<I, R>(entry_size: u32, records: I, string_table: Vec<u8>) -> Result<Self>
+    where I: IntoIterator<Item = R>,
+          R: Into<Vec<u8>>,
   {
-    let strings: Vec<String> = strings.into_iter().map(Into::into).collect();
-    Atr1 {
-      msbt: NonNull::dangling(),
+    let mut entry_count = 0;
+    let mut flat = Vec::new();
+    for record in records {
+      let record = record.into();
+      if record.len() != entry_size as usize {
+        return Err(Error::InvalidRecordSize(record.len()));
+      }
+      flat.extend(record);
+      entry_count += 1;
+    }
+    let mut atr1 = Atr1 {
       section: Section::new(*b"ATR1", 0),
-      string_count,
-      _unknown_1,
-      strings,
+      entry_count,
+      entry_size,
+      records: flat,
+      string_table,
+    };
+    atr1.update();
+    Ok(atr1)
+  }
+
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn entry_count(&self) -> u32 {
+    self.entry_count
+  }
+
+  /// The size of each record in bytes.
+  pub fn entry_size(&self) -> u32 {
+    self.entry_size
+  }
+
+  pub fn records(&self) -> impl Iterator<Item = &[u8]> {
+    (0..self.entry_count as usize).filter_map(move |index| self.record(index))
+  }
+
+  pub fn record(&self, index: usize) -> Option<&[u8]> {
+    self.record_range(index).map(|range| &self.records[range])
+  }
+
+  /// Replaces the record at `index`, which must be exactly [`entry_size`](Atr1::entry_size) bytes.
+  pub fn set_record<R: Into<Vec<u8>>>(&mut self, index: usize, record: R) -> Result<()> {
+    let record = record.into();
+    if record.len() != self.entry_size as usize {
+      return Err(Error::InvalidRecordSize(record.len()));
     }
+    let range = self.record_range(index).ok_or(Error::NoSuchRecord(index))?;
+    self.records[range].copy_from_slice(&record);
+    Ok(())
   }
 
-  pub fn msbt(&self) -> &Msbt {
-    unsafe { self.msbt.as_ref() }
+  fn record_range(&self, index: usize) -> Option<std::ops::Range<usize>> {
+    if index >= self.entry_count as usize {
+      return None;
+    }
+    let size = self.entry_size as usize;
+    Some(index * size..(index + 1) * size)
   }
 
-  pub fn section(&self) -> &Section {
-    &self.section
+  /// Extends the records with zeroed records up to `count`, moving string offsets along with the
+  /// string table.
+  pub(crate) fn extend_records(&mut self, count: usize, header: &Header) {
+    if count <= self.entry_count as usize {
+      return;
+    }
+    let fields = self.string_fields(header);
+    let moved = ((count - self.entry_count as usize) * self.entry_size as usize) as u32;
+    self.map_string_offsets(&fields, header, |offset| offset + moved);
+    self.records.resize(count * self.entry_size as usize, 0);
+    self.entry_count = count as u32;
+  }
+
+  /// Removes the record at `index`, if there is one, along with the strings that only it refers to.
+  pub(crate) fn remove_record(&mut self, index: usize, header: &Header) {
+    let range = match self.record_range(index) {
+      Some(range) => range,
+      None => return,
+    };
+    let fields = self.string_fields(header);
+    let table_offset = self.string_table_offset();
+    let mut removed: Vec<usize> = fields
+      .iter()
+      .map(|&field| Atr1::read_offset(&self.records[range.start + field..], header))
+      .filter(|&offset| offset != 0)
+      .map(|offset| (offset - table_offset) as usize)
+      .collect();
+
+    self.records.drain(range);
+    self.entry_count -= 1;
+    let entry_size = self.entry_size;
+    self.map_string_offsets(&fields, header, |offset| offset - entry_size);
+
+    // later strings move down when one is dropped, so drop them from the end
+    removed.sort_unstable();
+    removed.dedup();
+    let table_offset = self.string_table_offset();
+    for &start in removed.iter().rev() {
+      let offset = table_offset + start as u32;
+      if self.string_offsets(&fields, header).any(|x| x == offset) {
+        continue;
+      }
+      let len = self.string_len(start, header);
+      self.string_table.drain(start..start + len);
+      self.map_string_offsets(&fields, header, |x| if x > offset { x - len as u32 } else { x });
+    }
+  }
+
+  /// Finds the fields of the records that hold string offsets, by their position in a record.
+  ///
+  /// Without the attribute definitions, a field is taken to hold string offsets if every record has
+  /// either 0 or the start of a string in the table there, and at least one has a string.
+  fn string_fields(&self, header: &Header) -> Vec<usize> {
+    let entry_size = self.entry_size as usize;
+    let mut fields = Vec::new();
+    if self.string_table.is_empty() || entry_size < 4 {
+      return fields;
+    }
+    let mut start = 0;
+    while start + 4 <= entry_size {
+      let offsets = || self.records().map(move |record| Atr1::read_offset(&record[start..], header));
+      if offsets().any(|offset| offset != 0)
+        && offsets().all(|offset| offset == 0 || self.is_string_start(offset, header)) {
+        fields.push(start);
+        start += 4;
+      } else {
+        start += 1;
+      }
+    }
+    fields
+  }
+
+  fn is_string_start(&self, offset: u32, header: &Header) -> bool {
+    let unit_size = header.encoding.unit_size();
+    match offset.checked_sub(self.string_table_offset()) {
+      Some(start) => {
+        let start = start as usize;
+        start < self.string_table.len()
+          && start % unit_size == 0
+          && (start == 0 || self.string_table[start - unit_size..start].iter().all(|&b| b == 0))
+      },
+      None => false,
+    }
+  }
+
+  /// The non-zero offsets in the given string fields of every record.
+  fn string_offsets<'a>(&'a self, fields: &'a [usize], header: &'a Header) -> impl Iterator<Item = u32> + 'a {
+    self.records()
+      .flat_map(move |record| fields.iter().map(move |&field| Atr1::read_offset(&record[field..], header)))
+      .filter(|&offset| offset != 0)
+  }
+
+  fn map_string_offsets<F: Fn(u32) -> u32>(&mut self, fields: &[usize], header: &Header, f: F) {
+    let entry_size = self.entry_size as usize;
+    for record in 0..self.entry_count as usize {
+      for &field in fields {
+        let bytes = &mut self.records[record * entry_size + field..record * entry_size + field + 4];
+        let offset = Atr1::read_offset(bytes, header);
+        if offset != 0 {
+          header.endianness.write_u32(bytes, f(offset)).expect("writing to slice failed");
+        }
+      }
+    }
+  }
+
+  fn read_offset(bytes: &[u8], header: &Header) -> u32 {
+    header.endianness.read_u32(bytes).expect("reading from slice failed")
+  }
+
+  /// The length of the string at `start` in the string table, including its null terminator.
+  fn string_len(&self, start: usize, header: &Header) -> usize {
+    let unit_size = header.encoding.unit_size();
+    let rest = &self.string_table[start..];
+    rest.chunks(unit_size)
+      .position(|unit| unit.iter().all(|&b| b == 0))
+      .map(|i| (i + 1) * unit_size)
+      .unwrap_or(rest.len())
+  }
+
+  /// The raw bytes following the records.
+  pub fn string_table(&self) -> &[u8] {
+    &self.string_table
+  }
+
+  pub fn set_string_table<V: Into<Vec<u8>>>(&mut self, string_table: V) {
+    self.string_table = string_table.into();
+    self.update();
+  }
+
+  /// The offset of the string table from the start of the section's data, which is where string
+  /// offsets in records are counted from.
+  pub fn string_table_offset(&self) -> u32 {
+    (std::mem::size_of_val(&self.entry_count)
+      + std::mem::size_of_val(&self.entry_size)
+      + self.records.len()) as u32
+  }
+
+  fn string(&self, offset: u32, header: &Header) -> Result<String> {
+    let start = offset
+      .checked_sub(self.string_table_offset())
+      .filter(|&start| start as usize <= self.string_table.len())
+      .ok_or(Error::InvalidStringOffset(offset))? as usize;
+    let unit_size = header.encoding.unit_size();
+    let bytes = &self.string_table[start..];
+    let len = bytes.chunks(unit_size)
+      .position(|unit| unit.iter().all(|&b| b == 0))
+      .map(|i| i * unit_size)
+      .unwrap_or(bytes.len());
+    message::decode_string(&bytes[..len], header.encoding, header.endianness)
+  }
+
+  pub(crate) fn encode_attribute(&mut self, index: usize, def: &AttributeDef, value: AttributeValue, header: &Header) -> Result<()> {
+    let (encoding, endianness) = (header.encoding, header.endianness);
+    let start = def.offset as usize;
+    let record_len = self.record(index).ok_or(Error::NoSuchRecord(index))?.len();
+    if start + Atr1::value_size(def.value_type) > record_len {
+      return Err(Atr1::attribute_error(def, "attribute does not fit in the record"));
+    }
+
+    let mut bytes = Vec::with_capacity(4);
+    match (def.value_type, value) {
+      (ValueType::U8, AttributeValue::U8(x)) => bytes.push(x),
+      (ValueType::U16, AttributeValue::U16(x)) => endianness.write_u16(&mut bytes, x).map_err(Error::Io)?,
+      (ValueType::U32, AttributeValue::U32(x)) => endianness.write_u32(&mut bytes, x).map_err(Error::Io)?,
+      (ValueType::I8, AttributeValue::I8(x)) => bytes.push(x as u8),
+      (ValueType::I16, AttributeValue::I16(x)) => endianness.write_i16(&mut bytes, x).map_err(Error::Io)?,
+      (ValueType::I32, AttributeValue::I32(x)) => endianness.write_i32(&mut bytes, x).map_err(Error::Io)?,
+      (ValueType::F32, AttributeValue::F32(x)) => endianness.write_f32(&mut bytes, x).map_err(Error::Io)?,
+      (ValueType::List, AttributeValue::List(item)) => {
+        let i = def.items
+          .iter()
+          .position(|x| x == &item)
+          .ok_or_else(|| Atr1::attribute_error(def, format!("unknown item {:?}", item)))?;
+        bytes.push(i as u8);
+      },
+      (ValueType::String, AttributeValue::String(string)) => {
+        self.release_string(index, start, header);
+        let offset = self.insert_string(&message::encode_message(&string, encoding, endianness), header);
+        endianness.write_u32(&mut bytes, offset).map_err(Error::Io)?;
+      },
+      _ => return Err(Atr1::attribute_error(def, "wrong value type")),
+    }
+
+    let range = self.record_range(index).ok_or(Error::NoSuchRecord(index))?;
+    self.records[range]
+      .get_mut(start..start + bytes.len())
+      .ok_or_else(|| Atr1::attribute_error(def, "attribute does not fit in the record"))?
+      .copy_from_slice(&bytes);
+
+    self.update();
+    Ok(())
+  }
+
+  /// Removes the string that the string attribute at `start` in record `index` points to, if it is
+  /// the last string in the table and no other record field has its offset.
+  fn release_string(&mut self, index: usize, start: usize, header: &Header) {
+    let endianness = header.endianness;
+    let read = |bytes: &[u8]| endianness.read_u32(bytes).expect("reading from slice failed");
+    let record = self.record(index).expect("record was checked");
+    let offset = read(&record[start..start + 4]);
+    let table_start = match offset.checked_sub(self.string_table_offset()) {
+      Some(table_start) => table_start as usize,
+      None => return,
+    };
+
+    let unit_size = header.encoding.unit_size();
+    let is_terminator = |unit: &[u8]| unit.iter().all(|&b| b == 0);
+    let after_terminator = table_start == 0
+      || (table_start >= unit_size && is_terminator(&self.string_table[table_start - unit_size..table_start]));
+    let is_last = self.string_table
+      .get(table_start..)
+      .filter(|rest| !rest.is_empty() && rest.len() % unit_size == 0)
+      .map(|rest| rest.chunks(unit_size).position(is_terminator) == Some(rest.len() / unit_size - 1))
+      .unwrap_or(false);
+    if !after_terminator || !is_last {
+      return;
+    }
+
+    let shared = self.records()
+      .enumerate()
+      .any(|(i, record)| record
+        .windows(4)
+        .enumerate()
+        .any(|(j, bytes)| (i, j) != (index, start) && read(bytes) == offset));
+    if !shared {
+      self.string_table.truncate(table_start);
+    }
   }
 
-  pub fn string_count(&self) -> u32 {
-    self.string_count
+  /// Adds a null-terminated string to the string table, reusing an identical string if there is
+  /// one, and returns its offset.
+  fn insert_string(&mut self, encoded: &[u8], header: &Header) -> u32 {
+    let unit_size = header.encoding.unit_size();
+    let table = &self.string_table;
+    let existing = (0..=table.len().saturating_sub(encoded.len()))
+      .step_by(unit_size)
+      .filter(|&i| i == 0 || table[i - unit_size..i].iter().all(|&b| b == 0))
+      .find(|&i| table.get(i..i + encoded.len()) == Some(encoded));
+    let start = match existing {
+      Some(start) => start,
+      None => {
+        let start = self.string_table.len();
+        self.string_table.extend_from_slice(encoded);
+        start
+      },
+    };
+    self.string_table_offset() + start as u32
   }
 
-  pub fn unknown_1(&self) -> u32 {
-    self._unknown_1
+  pub(crate) fn find_def(msbp: &Msbp, name: &str) -> Result<AttributeDef> {
+    msbp.attribute_defs()
+      .into_iter()
+      .find(|def| def.name == name)
+      .ok_or_else(|| Error::UnknownAttribute(name.to_string()))
   }
 
-  pub fn strings(&self) -> Vec<&str> {
-    self.strings.iter().map(AsRef::as_ref).collect()
+  /// The number of bytes a value of this type takes up in a record.
+  pub(crate) fn value_size(value_type: ValueType) -> usize {
+    match value_type {
+      ValueType::U8 | ValueType::I8 | ValueType::List => 1,
+      ValueType::U16 | ValueType::I16 => 2,
+      ValueType::U32 | ValueType::I32 | ValueType::F32 | ValueType::String => 4,
+    }
+  }
+
+  fn attribute_error<R: Into<String>>(def: &AttributeDef, reason: R) -> Error {
+    Error::InvalidAttribute {
+      name: def.name.clone(),
+      reason: reason.into(),
+    }
+  }
+
+  fn decode_attribute(&self, index: usize, def: &AttributeDef, header: &Header) -> Result<AttributeValue> {
+    let record = self.record(index).ok_or(Error::NoSuchRecord(index))?;
+    let endianness = header.endianness;
+    let size = Atr1::value_size(def.value_type);
+    let start = def.offset as usize;
+    let bytes = record
+      .get(start..start + size)
+      .ok_or_else(|| Atr1::attribute_error(def, "attribute does not fit in the record"))?;
+
+    let value = match def.value_type {
+      ValueType::U8 => AttributeValue::U8(bytes[0]),
+      ValueType::U16 => AttributeValue::U16(endianness.read_u16(bytes).map_err(Error::Io)?),
+      ValueType::U32 => AttributeValue::U32(endianness.read_u32(bytes).map_err(Error::Io)?),
+      ValueType::I8 => AttributeValue::I8(bytes[0] as i8),
+      ValueType::I16 => AttributeValue::I16(endianness.read_i16(bytes).map_err(Error::Io)?),
+      ValueType::I32 => AttributeValue::I32(endianness.read_i32(bytes).map_err(Error::Io)?),
+      ValueType::F32 => AttributeValue::F32(endianness.read_f32(bytes).map_err(Error::Io)?),
+      ValueType::List => {
+        let item = def.items
+          .get(bytes[0] as usize)
+          .ok_or_else(|| Atr1::attribute_error(def, format!("no list item {}", bytes[0])))?;
+        AttributeValue::List(item.clone())
+      },
+      ValueType::String => AttributeValue::String(self.string(endianness.read_u32(bytes).map_err(Error::Io)?, header)?),
+    };
+    Ok(value)
+  }
+}
+
+impl Msbt {
+  /// Reads the null-terminated string at `offset` from the start of ATR1's data.
+  pub fn attribute_string(&self, offset: u32) -> Result<String> {
+    let atr1 = self.atr1.as_ref().ok_or(Error::InvalidStringOffset(offset))?;
+    atr1.string(offset, &self.header)
+  }
+
+  /// Decodes the ATR1 record at `index` into named values, using the attribute definitions in
+  /// `msbp`.
+  pub fn attributes(&self, index: usize, msbp: &Msbp) -> Result<AttributeSet> {
+    let atr1 = self.atr1.as_ref().ok_or(Error::NoSuchRecord(index))?;
+    let values = msbp.attribute_defs()
+      .into_iter()
+      .map(|def| {
+        let value = atr1.decode_attribute(index, &def, &self.header)?;
+        Ok((def.name, value))
+      })
+      .collect::<Result<_>>()?;
+    Ok(AttributeSet { values })
+  }
+
+  /// Decodes a single named attribute of the ATR1 record at `index`.
+  pub fn attribute(&self, index: usize, msbp: &Msbp, name: &str) -> Result<AttributeValue> {
+    let atr1 = self.atr1.as_ref().ok_or(Error::NoSuchRecord(index))?;
+    atr1.decode_attribute(index, &Atr1::find_def(msbp, name)?, &self.header)
+  }
+
+  /// Encodes a single named attribute into the ATR1 record at `index`.
+  ///
+  /// String values are encoded in the file's encoding and added to the string table, reusing an
+  /// identical string if there is one. The previous string is removed if nothing else refers to it
+  /// and it is last in the table.
+  pub fn set_attribute(&mut self, index: usize, msbp: &Msbp, name: &str, value: AttributeValue) -> Result<()> {
+    let def = Atr1::find_def(msbp, name)?;
+    let atr1 = self.atr1.as_mut().ok_or(Error::NoSuchRecord(index))?;
+    atr1.encode_attribute(index, &def, value, &self.header)?;
+    self.update();
+    Ok(())
+  }
+}
+
+/// A typed attribute value.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde_support", serde(rename_all = "snake_case"))]
+pub enum AttributeValue {
+  U8(u8),
+  U16(u16),
+  U32(u32),
+  I8(i8),
+  I16(i16),
+  I32(i32),
+  F32(f32),
+  String(String),
+  /// The name of the selected list item.
+  List(String),
+}
+
+/// The named attribute values of one ATR1 record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+pub struct AttributeSet {
+  values: Vec<(String, AttributeValue)>,
+}
+
+impl AttributeSet {
+  pub fn get(&self, name: &str) -> Option<&AttributeValue> {
+    self.values.iter().find(|(n, _)| n == name).map(|(_, value)| value)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &AttributeValue)> {
+    self.values.iter().map(|(name, value)| (name.as_str(), value))
+  }
+
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
   }
 }
 
@@ -61,14 +488,150 @@ impl Updates for Atr1 {
 
 impl CalculatesSize for Atr1 {
   fn calc_size(&self) -> usize {
-    let multiplier = match self.msbt().header().encoding() {
-      Encoding::Utf8 => 1,
-      Encoding::Utf16 => 2,
-    };
     self.section.calc_size()
-      + std::mem::size_of_val(&self.string_count)
-      + std::mem::size_of_val(&self._unknown_1)
-      + std::mem::size_of::<u32>() * self.strings.len() // offsets
-      + self.strings.iter().map(|x| x.as_bytes().len()).sum::<usize>() * multiplier // strings
+      + self.string_table_offset() as usize
+      + self.string_table.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Encoding, builder::MsbtBuilder, msbp::builder::MsbpBuilder};
+
+  use byteordered::Endianness;
+
+  fn sample() -> (Msbt, Msbp) {
+    let msbp = MsbpBuilder::new(Endianness::Little, 1)
+      .attribute("speaker", ValueType::String, 0)
+      .attribute("voice", ValueType::U32, 4)
+      .build()
+      .unwrap();
+    let msbt = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(1))
+      .message("a", "A")
+      .attributes(vec![0; 8])
+      .message("b", "B")
+      .attributes(vec![0; 8])
+      .build()
+      .unwrap();
+    (msbt, msbp)
+  }
+
+  fn speaker(msbt: &Msbt, msbp: &Msbp, index: usize) -> AttributeValue {
+    msbt.attribute(index, msbp, "speaker").unwrap()
+  }
+
+  fn table_len(msbt: &Msbt) -> usize {
+    msbt.atr1().unwrap().string_table().len()
+  }
+
+  #[test]
+  fn replaces_overwritten_strings() {
+    let (mut msbt, msbp) = sample();
+    for name in &["Link", "Zelda", "Ganondorf", "Link"] {
+      msbt.set_attribute(0, &msbp, "speaker", AttributeValue::String(name.to_string())).unwrap();
+    }
+    assert_eq!(speaker(&msbt, &msbp, 0), AttributeValue::String("Link".to_string()));
+    assert_eq!(table_len(&msbt), 10);
+    assert_eq!(msbt.atr1().unwrap().section().size as usize, msbt.atr1().unwrap().calc_size() - 16);
+  }
+
+  #[test]
+  fn keeps_shared_strings() {
+    let (mut msbt, msbp) = sample();
+    msbt.set_attribute(0, &msbp, "speaker", AttributeValue::String("Link".to_string())).unwrap();
+    msbt.set_attribute(1, &msbp, "speaker", AttributeValue::String("Link".to_string())).unwrap();
+    assert_eq!(table_len(&msbt), 10);
+
+    msbt.set_attribute(1, &msbp, "speaker", AttributeValue::String("Zelda".to_string())).unwrap();
+    assert_eq!(table_len(&msbt), 22);
+    assert_eq!(speaker(&msbt, &msbp, 0), AttributeValue::String("Link".to_string()));
+    assert_eq!(speaker(&msbt, &msbp, 1), AttributeValue::String("Zelda".to_string()));
+
+    // an offset stored in another field keeps its string too
+    let offset = msbt.atr1().unwrap().record(1).unwrap()[..4].to_vec();
+    let voice = u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]);
+    msbt.set_attribute(0, &msbp, "voice", AttributeValue::U32(voice)).unwrap();
+    msbt.set_attribute(1, &msbp, "speaker", AttributeValue::String("Link".to_string())).unwrap();
+    assert_eq!(table_len(&msbt), 22);
+  }
+
+  fn set_speakers(msbt: &mut Msbt, msbp: &Msbp, names: &[&str]) {
+    for (i, name) in names.iter().enumerate() {
+      msbt.set_attribute(i, msbp, "speaker", AttributeValue::String(name.to_string())).unwrap();
+      msbt.set_attribute(i, msbp, "voice", AttributeValue::U32(i as u32 + 1)).unwrap();
+    }
+  }
+
+  #[test]
+  fn moves_strings_when_inserting_messages() {
+    let (mut msbt, msbp) = sample();
+    set_speakers(&mut msbt, &msbp, &["Link", "Zelda"]);
+    msbt.insert_message("c", "C").unwrap();
+    msbt.insert_messages(vec![("d", "D"), ("e", "E")]).unwrap();
+
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+    let msbt = Msbt::from_reader(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(speaker(&msbt, &msbp, 0), AttributeValue::String("Link".to_string()));
+    assert_eq!(speaker(&msbt, &msbp, 1), AttributeValue::String("Zelda".to_string()));
+    assert_eq!(msbt.attribute(1, &msbp, "voice").unwrap(), AttributeValue::U32(2));
+    assert_eq!(msbt.atr1().unwrap().record(4), Some(&[0; 8][..]));
+  }
+
+  #[test]
+  fn drops_strings_when_removing_messages() {
+    let (mut msbt, msbp) = sample();
+    msbt.insert_message("c", "C").unwrap();
+    set_speakers(&mut msbt, &msbp, &["Link", "Zelda", "Zelda"]);
+    assert_eq!(table_len(&msbt), 22);
+
+    msbt.remove_message("a").unwrap();
+    assert_eq!(speaker(&msbt, &msbp, 0), AttributeValue::String("Zelda".to_string()));
+    assert_eq!(speaker(&msbt, &msbp, 1), AttributeValue::String("Zelda".to_string()));
+    assert_eq!(table_len(&msbt), 12);
+
+    msbt.remove_message("b").unwrap();
+    assert_eq!(speaker(&msbt, &msbp, 0), AttributeValue::String("Zelda".to_string()));
+    assert_eq!(msbt.attribute(0, &msbp, "voice").unwrap(), AttributeValue::U32(3));
+    assert_eq!(table_len(&msbt), 12);
+  }
+
+  #[test]
+  fn rejects_strings_with_partial_code_units() {
+    let (mut msbt, msbp) = sample();
+    {
+      let mut atr1 = msbt.atr1_mut().unwrap();
+      let offset = atr1.string_table_offset();
+      atr1.set_record(0, offset.to_le_bytes().iter().copied().chain(vec![0; 4]).collect::<Vec<_>>()).unwrap();
+      atr1.set_string_table(vec![76, 0, 105]);
+    }
+    assert!(matches!(
+      msbt.attribute(0, &msbp, "speaker"),
+      Err(Error::UnexpectedEndOfMessage(3)),
+    ));
+  }
+
+  #[test]
+  fn reads_empty_records() {
+    let msbt = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(1))
+      .atr1(Atr1::new_unlinked(0, Vec::<Vec<u8>>::new(), Vec::new()).unwrap())
+      .message("a", "A")
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+
+    // a count no file could hold records for, were they not empty
+    let atr1 = bytes.windows(4).position(|w| w == b"ATR1").unwrap();
+    bytes[atr1 + 16..atr1 + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+    let msbt = Msbt::from_reader(std::io::Cursor::new(&bytes)).unwrap();
+    let atr1 = msbt.atr1().unwrap();
+    assert_eq!(atr1.entry_count(), u32::MAX);
+    assert_eq!(atr1.record(1_000_000), Some(&[][..]));
+
+    let mut written = Vec::new();
+    msbt.write_to(&mut written).unwrap();
+    assert_eq!(written, bytes);
   }
 }
@@ -1,31 +1,41 @@
 use crate::{
   Msbt,
+  error::{Error, Result},
   traits::{CalculatesSize, Updates},
-  updater::Updater,
 };
 use super::Section;
 
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
 use std::{
   borrow::Cow,
-  ptr::NonNull,
+  collections::HashMap,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Lbl1 {
-  pub(crate) msbt: NonNull<Msbt>,
   pub(crate) section: Section,
   pub(crate) group_count: u32,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub(crate) groups: Vec<Group>,
   pub(crate) labels: Vec<Label>,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
+  pub(crate) index: LabelIndex,
 }
 
 impl Lbl1 {
-  pub fn msbt(&self) -> &Msbt {
-    unsafe { self.msbt.as_ref() }
-  }
-
-  fn msbt_mut(&mut self) -> Updater<Msbt> {
-    Updater::new(unsafe { self.msbt.as_mut() })
+  pub(crate) fn new(section: Section, group_count: u32, groups: Vec<Group>, labels: Vec<Label>) -> Self {
+    let mut lbl1 = Lbl1 {
+      section,
+      group_count,
+      groups,
+      labels,
+      index: LabelIndex::default(),
+    };
+    lbl1.reindex();
+    lbl1
   }
 
   pub fn section(&self) -> &Section {
@@ -44,26 +54,106 @@ impl Lbl1 {
     &self.labels
   }
 
-  pub fn labels_mut(&mut self) -> &mut [Label] {
-    &mut self.labels
+  /// Finds a label by name, searching only its hash group.
+  pub fn label(&self, name: &str) -> Option<&Label> {
+    self.position(name).map(|i| &self.labels[i])
+  }
+
+  /// Finds the label pointing to the TXT2 string at `index`.
+  pub fn label_for_index(&self, index: u32) -> Option<&Label> {
+    self.index.by_string.get(&index).map(|&i| &self.labels[i])
+  }
+
+  pub(crate) fn position(&self, name: &str) -> Option<usize> {
+    if self.group_count == 0 {
+      return None;
+    }
+    self.index.groups
+      .get(Label::hash(name, self.group_count) as usize)?
+      .iter()
+      .copied()
+      .find(|&i| self.labels[i].name == name)
+  }
+
+  fn reindex(&mut self) {
+    let mut index = LabelIndex {
+      groups: vec![Vec::new(); self.group_count as usize],
+      by_string: HashMap::with_capacity(self.labels.len()),
+    };
+    for (i, label) in self.labels.iter().enumerate() {
+      if let Some(group) = index.groups.get_mut(label.checksum as usize) {
+        group.push(i);
+      }
+      index.by_string.entry(label.index).or_insert(i);
+    }
+    self.index = index;
+  }
+
+  /// Renames the label at `index`, moving it to the hash group of its new name.
+  pub fn rename<S>(&mut self, index: usize, name: S) -> Result<()>
+    where S: Into<String>,
+  {
+    let name = name.into();
+    if name.len() > u8::MAX as usize {
+      return Err(Error::LabelTooLong(name));
+    }
+    if self.position(&name).is_some_and(|i| i != index) {
+      return Err(Error::DuplicateLabel(name));
+    }
+    let group_count = self.group_count;
+    let label = self.labels.get_mut(index).ok_or(Error::NoSuchEntry(index))?;
+    label.checksum = Label::hash(&name, group_count);
+    label.name = name;
+    self.update();
+    Ok(())
+  }
+
+  /// Changes the number of hash groups, moving every label to its group under the new count.
+  pub fn rehash(&mut self, group_count: u32) -> Result<()> {
+    if group_count == 0 {
+      return Err(Error::InvalidGroupCount(group_count));
+    }
+    self.group_count = group_count;
+    self.groups = vec![Group { label_count: 0, offset: 0 }; group_count as usize];
+    for label in &mut self.labels {
+      label.checksum = Label::hash(&label.name, group_count);
+    }
+    self.update();
+    Ok(())
   }
 
   fn update_group_offsets(&mut self) {
+    let mut counts = vec![0; self.groups.len()];
+    let mut sizes = vec![0; self.groups.len()];
+    for label in &self.labels {
+      let i = label.checksum as usize;
+      if i < counts.len() {
+        counts[i] += 1;
+        sizes[i] += label.calc_size() as u32;
+      }
+    }
+
     let mut total = 0;
     let group_len = self.groups.len() as u32;
     for (i, group) in self.groups.iter_mut().enumerate() {
       group.offset = group_len * group.calc_size() as u32
         + std::mem::size_of::<u32>() as u32 // group count
         + total;
-      total = self.labels
-        .iter()
-        .filter(|x| x.checksum == i as u32)
-        .fold(total, |current, lbl| current + lbl.calc_size() as u32);
+      group.label_count = counts[i];
+      total += sizes[i];
     }
   }
 }
 
-#[derive(Debug)]
+/// Positions in [`Lbl1::labels`] by hash group and by TXT2 index, rebuilt whenever LBL1 is
+/// updated.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LabelIndex {
+  groups: Vec<Vec<usize>>,
+  by_string: HashMap<u32, usize>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Group {
   pub(crate) label_count: u32,
   pub(crate) offset: u32,
@@ -79,124 +169,49 @@ impl Group {
   }
 }
 
-#[derive(Debug, Clone)]
+/// A label naming a TXT2 string.
+///
+/// Use [`Msbt::label_value`](crate::Msbt::label_value) to look up its string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Label {
-  pub(crate) lbl1: NonNull<Lbl1>,
   pub(crate) name: String,
   pub(crate) index: u32,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub(crate) checksum: u32,
 }
 
 impl Label {
   pub(crate) const HASH_MAGIC: u32 = 0x492;
 
-  fn lbl1(&self) -> &Lbl1 {
-    unsafe { self.lbl1.as_ref() }
-  }
-
-  fn lbl1_mut(&mut self) -> Updater<Lbl1> {
-    Updater::new(unsafe { self.lbl1.as_mut() })
-  }
-
-  pub(crate) fn update_checksum(&mut self) {
-    let hash: u32 = self.name.as_bytes()
+  /// Calculates the hash group of a label name.
+  pub(crate) fn hash(name: &str, group_count: u32) -> u32 {
+    let hash: u32 = name.as_bytes()
       .iter()
       .fold(0, |hash, b| hash.overflowing_mul(Label::HASH_MAGIC).0.overflowing_add(u32::from(*b)).0);
-    self.checksum = hash % self.lbl1().group_count();
+    hash % group_count
   }
 
   pub fn name(&self) -> &str {
     &self.name
   }
 
-  pub fn set_name<S>(&mut self, name: S)
-    where S: Into<String>,
-  {
-    self.name = name.into();
-    self.update_checksum();
-  }
-
+  /// The index of this label's string in TXT2.
   pub fn index(&self) -> u32 {
     self.index
   }
 
+  /// The hash group this label is stored in.
   pub fn checksum(&self) -> u32 {
     self.checksum
   }
-
-  /// Gets the value of this label.
-  ///
-  /// Note that the value is not guaranteed to exist. The Msbt containing the Lbl1 of this label
-  /// will have its Txt2 checked for this label's index, then that string returned if it exists.
-  pub fn value(&self) -> Option<Cow<str>> {
-    self.lbl1().msbt().txt2
-      .as_ref()
-      .and_then(|t| t.strings().ok())
-      .and_then(|ss| ss.get(self.index as usize).map(Clone::clone))
-  }
-
-  /// Sets the value of this label.
-  ///
-  /// This checks the Txt2 of the Msbt containing the Lbl1 of this label for this label's index,
-  /// then sets that index if it exists.
-  pub fn set_value<S: Into<String>>(&mut self, val: S) -> Result<(), ()> {
-    let string = val.into();
-    self.set_value_raw(string)
-  }
-
-  /// Gets the value of this label.
-  ///
-  /// # Panics
-  ///
-  /// This method will panic is the Msbt containing this label's Lbl1 does not have a Txt2 or if
-  /// that Txt2 does not have a string at this label's index.
-  pub unsafe fn value_unchecked(&self) -> Cow<str> {
-    self.lbl1().msbt().txt2.as_ref().unwrap().strings().unwrap()[self.index as usize].clone()
-  }
-
-  pub fn value_raw(&self) -> Option<&[u8]> {
-    self.lbl1().msbt().txt2
-      .as_ref()
-      .and_then(|t| t.raw_strings.get(self.index as usize).map(AsRef::as_ref))
-  }
-
-  pub unsafe fn value_raw_unchecked(&self) -> &[u8] {
-    &self.lbl1().msbt().txt2.as_ref().unwrap().raw_strings[self.index as usize]
-  }
-
-  /// Sets the raw value of this label.
-  ///
-  /// This checks the Txt2 of the Msbt containing the Lbl1 of this label for this label's index,
-  /// then sets that index if it exists.
-  pub fn set_value_raw<S: Into<Vec<u8>>>(&mut self, val: S) -> Result<(), ()> {
-    let bytes = val.into();
-    let index = self.index as usize;
-
-    let mut lbl1_mut = self.lbl1_mut();
-    let mut msbt_mut = lbl1_mut.msbt_mut();
-    let txt2 = msbt_mut.txt2.as_mut();
-
-    if let Some(txt2) = txt2 {
-      let txt2_raw = txt2.raw_strings.get_mut(index as usize);
-      if let Some(txt2_raw) = txt2_raw {
-        *txt2_raw = bytes;
-        return Ok(());
-      }
-    }
-
-    Err(())
-  }
 }
 
 impl Updates for Lbl1 {
   fn update(&mut self) {
     self.section.size = self.calc_size() as u32 - self.section.calc_size() as u32;
     self.update_group_offsets();
-    let mut msbt_mut = self.msbt_mut();
-    let txt2 = msbt_mut.txt2.as_mut();
-    if let Some(txt2) = txt2 {
-      txt2.update();
-    }
+    self.reindex();
   }
 }
 
@@ -218,7 +233,111 @@ impl CalculatesSize for Group {
 impl CalculatesSize for Label {
   fn calc_size(&self) -> usize {
     std::mem::size_of::<u8>() // name length
-      + self.name.as_bytes().len()
+      + self.name.len()
       + std::mem::size_of_val(&self.index)
   }
 }
+
+impl Msbt {
+  /// Decodes the string for the label `name`.
+  pub fn get(&self, name: &str) -> Result<Option<Cow<'_, str>>> {
+    match self.label(name) {
+      Some(label) => self.label_value(label).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  /// The raw string for the label `name`.
+  pub fn get_raw(&self, name: &str) -> Option<&[u8]> {
+    self.raw_string(self.label(name)?.index)
+  }
+
+  /// Finds a label by name, searching only its hash group.
+  pub fn label(&self, name: &str) -> Option<&Label> {
+    self.lbl1.as_ref()?.label(name)
+  }
+
+  /// Finds the label pointing to the TXT2 string at `index`.
+  pub fn label_for_index(&self, index: u32) -> Option<&Label> {
+    self.lbl1.as_ref()?.label_for_index(index)
+  }
+
+  pub fn contains_label(&self, name: &str) -> bool {
+    self.label(name).is_some()
+  }
+
+  /// Renames the label `name`, moving it to the hash group of its new name.
+  pub fn rename_label<S: Into<String>>(&mut self, name: &str, new_name: S) -> Result<()> {
+    let lbl1 = self.lbl1.as_mut().ok_or_else(|| Error::NoSuchLabel(name.to_string()))?;
+    let index = lbl1.position(name).ok_or_else(|| Error::NoSuchLabel(name.to_string()))?;
+    lbl1.rename(index, new_name)?;
+    self.update();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_util::sample;
+
+  use std::io::Cursor;
+
+  fn read_sample() -> Msbt {
+    Msbt::from_reader(Cursor::new(sample())).unwrap()
+  }
+
+  #[test]
+  fn looks_up_renamed_labels() {
+    let mut msbt = read_sample();
+    msbt.rename_label("greeting", "hello").unwrap();
+
+    assert!(msbt.label("greeting").is_none());
+    assert_eq!(msbt.label("hello").map(Label::index), Some(0));
+    assert_eq!(msbt.label_for_index(0).map(Label::name), Some("hello"));
+    assert_eq!(msbt.get("hello").unwrap().as_deref(), Some("Hello"));
+    assert!(matches!(msbt.rename_label("greeting", "hi"), Err(Error::NoSuchLabel(_))));
+
+    msbt.remove_message("hello").unwrap();
+    assert_eq!(msbt.label_for_index(0).map(Label::name), Some("farewell"));
+    assert!(msbt.label_for_index(1).is_none());
+  }
+
+  #[test]
+  fn rehashes_labels() {
+    let mut msbt = read_sample();
+    {
+      let mut lbl1 = msbt.lbl1_mut().unwrap();
+      assert!(matches!(lbl1.rehash(0), Err(Error::InvalidGroupCount(0))));
+      lbl1.rehash(101).unwrap();
+    }
+    let lbl1 = msbt.lbl1().unwrap();
+    assert_eq!(lbl1.groups().len(), 101);
+    for label in lbl1.labels() {
+      assert_eq!(label.checksum(), Label::hash(label.name(), 101));
+      assert_eq!(lbl1.groups()[label.checksum() as usize].label_count(), 1);
+    }
+
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+    let msbt = Msbt::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(msbt.lbl1().unwrap().group_count(), 101);
+    assert_eq!(msbt.get("farewell").unwrap().as_deref(), Some("Bye"));
+  }
+
+  #[test]
+  fn renames_labels() {
+    let mut msbt = read_sample();
+    let mut lbl1 = msbt.lbl1_mut().unwrap();
+    let greeting = lbl1.position("greeting").unwrap();
+    assert!(matches!(lbl1.rename(greeting, "farewell"), Err(Error::DuplicateLabel(_))));
+    assert!(matches!(lbl1.rename(greeting, "x".repeat(256)), Err(Error::LabelTooLong(_))));
+    assert!(matches!(lbl1.rename(5, "other"), Err(Error::NoSuchEntry(5))));
+    lbl1.rename(greeting, "greeting").unwrap();
+
+    lbl1.rename(greeting, "welcome").unwrap();
+    let label = lbl1.label("welcome").unwrap();
+    assert_eq!(label.checksum(), Label::hash("welcome", lbl1.group_count()));
+    assert_eq!(lbl1.groups().iter().map(Group::label_count).sum::<u32>(), 2);
+  }
+}
@@ -1,18 +1,16 @@
-use crate::{
-  Msbt,
-  traits::CalculatesSize,
-};
+use crate::traits::{CalculatesSize, Updates};
 use super::Section;
 
-use std::{
-  collections::BTreeMap,
-  ptr::NonNull,
-};
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug)]
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Nli1 {
-  pub(crate) msbt: NonNull<Msbt>,
   pub(crate) section: Section,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub(crate) id_count: u32,
   pub(crate) global_ids: BTreeMap<u32, u32>,
 }
@@ -21,17 +19,12 @@ impl Nli1 {
   pub fn new_unlinked(id_count: u32, global_ids: BTreeMap<u32, u32>) -> Self {
     let size = std::mem::size_of_val(&id_count) + std::mem::size_of::<u32>() * 2 * global_ids.len();
     Nli1 {
-      msbt: NonNull::dangling(),
       section: Section::new(*b"NLI1", size as u32),
       id_count,
       global_ids,
     }
   }
 
-  pub fn msbt(&self) -> &Msbt {
-    unsafe { self.msbt.as_ref() }
-  }
-
   pub fn section(&self) -> &Section {
     &self.section
   }
@@ -40,11 +33,19 @@ impl Nli1 {
     self.id_count
   }
 
+  /// Global message ids, keyed by TXT2 index.
   pub fn global_ids(&self) -> &BTreeMap<u32, u32> {
     &self.global_ids
   }
 }
 
+impl Updates for Nli1 {
+  fn update(&mut self) {
+    self.id_count = self.global_ids.len() as u32;
+    self.section.size = (self.calc_size() - self.section.calc_size()) as u32;
+  }
+}
+
 impl CalculatesSize for Nli1 {
   fn calc_size(&self) -> usize {
     let mut base = self.section.calc_size();
@@ -1,43 +1,131 @@
 use crate::{
   Msbt,
-  traits::CalculatesSize,
+  error::{Error, Result},
+  msbp::Msbp,
+  traits::{CalculatesSize, Updates},
 };
 use super::Section;
 
-use std::ptr::NonNull;
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug)]
+/// The style of each message, as an index into an MSBP's styles.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Tsy1 {
-  pub(crate) msbt: NonNull<Msbt>,
   pub(crate) section: Section,
-  pub(crate) _unknown: Vec<u8>, // tons of unknown data
+  pub(crate) styles: Vec<u32>,
 }
 
 impl Tsy1 {
-  pub fn new_unlinked<V: Into<Vec<u8>>>(unknown_bytes: V) -> Self {
-    let bytes = unknown_bytes.into();
-    Tsy1 {
-      msbt: NonNull::dangling(),
-      section: Section::new(*b"TSY1", bytes.len() as u32),
-      _unknown: bytes,
+  pub fn new_unlinked<V: Into<Vec<u32>>>(styles: V) -> Self {
+    let mut tsy1 = Tsy1 {
+      section: Section::new(*b"TSY1", 0),
+      styles: styles.into(),
+    };
+    tsy1.update();
+    tsy1
+  }
+
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  /// The style index of each message, in TXT2 order.
+  pub fn styles(&self) -> &[u32] {
+    &self.styles
+  }
+
+  pub fn style(&self, index: usize) -> Option<u32> {
+    self.styles.get(index).copied()
+  }
+
+  /// Sets the style of the message at `index`.
+  ///
+  /// See [`Msbt::set_style`] to extend this section to the TXT2 string count first.
+  pub fn set_style(&mut self, index: usize, style: u32) -> Result<()> {
+    match self.styles.get_mut(index) {
+      Some(s) => *s = style,
+      None => return Err(Error::NoSuchEntry(index)),
     }
+    Ok(())
   }
 
-  pub fn msbt(&self) -> &Msbt {
-    unsafe { self.msbt.as_ref() }
+  pub fn set_styles<V: Into<Vec<u32>>>(&mut self, styles: V) {
+    self.styles = styles.into();
+    self.update();
   }
 
-  pub fn section(&self) -> &Section {
-    &self.section
+  pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+    self.styles.iter().copied()
   }
 
-  pub fn unknown_bytes(&self) -> &[u8] {
-    &self._unknown
+  /// The name of the style of the message at `index`, from the SLB1 section of `msbp`.
+  pub fn style_name<'a>(&self, index: usize, msbp: &'a Msbp) -> Option<&'a str> {
+    msbp.slb1()?.name_of(self.style(index)?)
+  }
+
+  /// Sets the style of the message at `index` to the style named `name` in `msbp`.
+  pub fn set_style_by_name(&mut self, index: usize, msbp: &Msbp, name: &str) -> Result<()> {
+    let style = msbp.slb1()
+      .and_then(|slb1| slb1.index_of(name))
+      .ok_or_else(|| Error::UnknownStyle(name.to_string()))?;
+    self.set_style(index, style)
+  }
+}
+
+impl Msbt {
+  /// Sets the style of the message at `index`.
+  ///
+  /// If TSY1 has fewer entries than TXT2 has strings, it is first extended with style 0 up to the
+  /// string count. Fails if the file has no TSY1.
+  pub fn set_style(&mut self, index: usize, style: u32) -> Result<()> {
+    let string_count = self.txt2.as_ref().map(|t| t.string_count() as usize).unwrap_or(0);
+    let tsy1 = self.tsy1.as_mut().ok_or(Error::MissingSection(*b"TSY1"))?;
+    if index >= tsy1.styles.len() && index < string_count {
+      tsy1.styles.resize(string_count, 0);
+      tsy1.update();
+    }
+    tsy1.set_style(index, style)?;
+    self.update();
+    Ok(())
+  }
+
+  /// Whether there is exactly one TSY1 style for each TXT2 string.
+  pub fn styles_match_txt2(&self) -> bool {
+    let style_count = self.tsy1.as_ref().map(|t| t.styles.len());
+    style_count.is_some() && style_count == self.txt2.as_ref().map(|t| t.string_count() as usize)
+  }
+}
+
+impl Updates for Tsy1 {
+  fn update(&mut self) {
+    self.section.size = (self.styles.len() * std::mem::size_of::<u32>()) as u32;
   }
 }
 
 impl CalculatesSize for Tsy1 {
   fn calc_size(&self) -> usize {
-    self.section.calc_size() + self._unknown.len()
+    self.section.calc_size() + self.styles.len() * std::mem::size_of::<u32>()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{SectionTag, test_util::sample};
+
+  use std::io::Cursor;
+
+  #[test]
+  fn sets_styles() {
+    let mut msbt = Msbt::from_reader(Cursor::new(sample())).unwrap();
+    assert!(matches!(msbt.set_style(0, 1), Err(Error::MissingSection(magic)) if &magic == b"TSY1"));
+
+    msbt.tsy1 = Some(Tsy1::new_unlinked(Vec::new()));
+    msbt.section_order.push(SectionTag::Tsy1);
+    msbt.set_style(1, 2).unwrap();
+    assert_eq!(msbt.tsy1().unwrap().styles(), &[0, 2]);
+    assert!(matches!(msbt.set_style(2, 1), Err(Error::NoSuchEntry(2))));
   }
 }
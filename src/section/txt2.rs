@@ -1,29 +1,39 @@
 use crate::{
   Msbt,
   Encoding,
-  error::{Error, Result},
+  SectionTag,
+  error::{Error, ErrorContext, ErrorItem, Result},
+  markup,
+  message::{self, Segment},
+  schema::TagSchema,
   traits::{CalculatesSize, Updates},
 };
-use super::Section;
+use super::{
+  Section,
+  lbl1::Label,
+};
 
-use byteordered::Endian;
+use std::borrow::Cow;
 
-use std::{
-  borrow::Cow,
-  ptr::NonNull,
-};
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
 pub struct Txt2 {
-  pub(crate) msbt: NonNull<Msbt>,
   pub(crate) section: Section,
+  #[cfg_attr(feature = "serde_support", serde(skip))]
   pub(crate) string_count: u32,
   pub(crate) raw_strings: Vec<Vec<u8>>,
 }
 
 impl Txt2 {
-  pub fn msbt(&self) -> &Msbt {
-    unsafe { self.msbt.as_ref() }
+  pub(crate) fn empty() -> Self {
+    Txt2 {
+      section: Section::new(*b"TXT2", 0),
+      string_count: 0,
+      raw_strings: Vec::new(),
+    }
   }
 
   pub fn section(&self) -> &Section {
@@ -34,64 +44,192 @@ impl Txt2 {
     self.string_count
   }
 
-  pub fn strings(&self) -> Result<Vec<Cow<str>>> {
-    match self.msbt().header.encoding {
-      Encoding::Utf16 => {
-        self.raw_strings
-          .iter()
-          .map(|r| r.chunks(2)
-            .map(|bs| self.msbt().header.endianness.read_u16(bs).expect("reading from chunk failed"))
-            .collect::<Vec<u16>>())
-          .map(|s| String::from_utf16(&s).map(Cow::from).map_err(Error::InvalidUtf16))
-          .collect()
-      },
-      Encoding::Utf8 => {
-        self.raw_strings
-          .iter()
-          .map(|r| std::str::from_utf8(r.as_slice()).map(Cow::from).map_err(Error::InvalidBorrowedUtf8))
-          .collect()
-      }
-    }
+  pub fn raw_strings(&self) -> &[Vec<u8>] {
+    &self.raw_strings
+  }
+
+  fn string_error(index: usize, error: Error) -> Error {
+    error.with_context(ErrorContext {
+      offset: None,
+      section: Some(*b"TXT2"),
+      item: Some(ErrorItem::String(index as u32)),
+    })
+  }
+}
+
+/// Reading and writing TXT2 strings in the file's encoding.
+///
+/// Strings are stored with their null terminators. A single trailing terminator is removed when
+/// decoding to `str` and added when encoding from it, while the raw methods use the bytes as they
+/// are. Setting strings creates TXT2 if the file does not have one.
+impl Msbt {
+  /// Decodes the string at `index`.
+  pub fn string(&self, index: u32) -> Result<Cow<'_, str>> {
+    let raw = self.raw_string(index).ok_or(Error::NoSuchString(index))?;
+    self.decode(raw).map_err(|e| Txt2::string_error(index as usize, e))
   }
 
+  pub fn raw_string(&self, index: u32) -> Option<&[u8]> {
+    self.txt2.as_ref()?.raw_strings.get(index as usize).map(AsRef::as_ref)
+  }
+
+  /// Parses the string at `index` into text and control tag segments.
+  pub fn string_segments(&self, index: u32) -> Result<Vec<Segment>> {
+    let raw = self.raw_string(index).ok_or(Error::NoSuchString(index))?;
+    Segment::parse(raw, self.header.encoding, self.header.endianness)
+      .map_err(|e| Txt2::string_error(index as usize, e))
+  }
+
+  /// Decodes the string that `label` points to.
+  pub fn label_value(&self, label: &Label) -> Result<Cow<'_, str>> {
+    self.string(label.index)
+  }
+
+  /// Encodes `value` in the file's encoding and sets it as the string at `index`.
+  pub fn set_string<S: AsRef<str>>(&mut self, index: u32, value: S) -> Result<()> {
+    let raw = message::encode_message(value.as_ref(), self.header.encoding, self.header.endianness);
+    self.set_raw_string(index, raw)
+  }
+
+  pub fn set_raw_string<V: Into<Vec<u8>>>(&mut self, index: u32, value: V) -> Result<()> {
+    let raw = self.txt2
+      .as_mut()
+      .and_then(|t| t.raw_strings.get_mut(index as usize))
+      .ok_or(Error::NoSuchString(index))?;
+    *raw = value.into();
+    self.update();
+    Ok(())
+  }
+
+  /// Sets the string at `index` from text and control tag segments.
+  ///
+  /// A null terminator is appended, unless the segments end with
+  /// [`Segment::Unterminated`].
+  pub fn set_string_segments(&mut self, index: u32, segments: &[Segment]) -> Result<()> {
+    let raw = Segment::encode(segments, self.header.encoding, self.header.endianness)
+      .map_err(|e| Txt2::string_error(index as usize, e))?;
+    self.set_raw_string(index, raw)
+  }
+
+  /// Decodes every string.
+  pub fn strings(&self) -> Result<Vec<Cow<'_, str>>> {
+    self.raw_strings()
+      .iter()
+      .enumerate()
+      .map(|(i, r)| self.decode(r).map_err(|e| Txt2::string_error(i, e)))
+      .collect()
+  }
+
+  /// Replaces every string, encoding each in the file's encoding.
   pub fn set_strings<I, S>(&mut self, strings: I)
     where I: IntoIterator<Item = S>,
-          S: Into<String>,
+          S: AsRef<str>,
+  {
+    let (encoding, endianness) = (self.header.encoding, self.header.endianness);
+    self.txt2_or_insert().raw_strings = strings.into_iter()
+      .map(|s| message::encode_message(s.as_ref(), encoding, endianness))
+      .collect();
+    self.update();
+  }
+
+  /// Parses every string into text and control tag segments.
+  pub fn segments(&self) -> Result<Vec<Vec<Segment>>> {
+    self.raw_strings()
+      .iter()
+      .enumerate()
+      .map(|(i, r)| Segment::parse(r, self.header.encoding, self.header.endianness).map_err(|e| Txt2::string_error(i, e)))
+      .collect()
+  }
+
+  /// Sets the raw strings, checking that each one is made of valid text and control tags.
+  pub fn set_raw_strings<I, S>(&mut self, strings: I) -> Result<()>
+    where I: IntoIterator<Item = S>,
+          S: Into<Vec<u8>>,
   {
-    match self.msbt().header.encoding {
-      Encoding::Utf16 => {
-        let mut buf = [0; 2];
-        self.raw_strings = strings.into_iter()
-          .map(Into::into)
-          .map(|s| {
-            s.encode_utf16()
-              .flat_map(|u| {
-                self.msbt().header.endianness.write_u16(&mut buf[..], u).expect("failed to write to array");
-                buf.to_vec()
-              })
-              .collect()
-          })
-          .collect();
-      },
-      Encoding::Utf8 => self.raw_strings = strings.into_iter()
-        .map(Into::into)
-        .map(String::into_bytes)
-        .collect(),
+    let raw_strings: Vec<Vec<u8>> = strings.into_iter().map(Into::into).collect();
+    for (i, raw) in raw_strings.iter().enumerate() {
+      Segment::parse(raw, self.header.encoding, self.header.endianness).map_err(|e| Txt2::string_error(i, e))?;
     }
+    self.txt2_or_insert().raw_strings = raw_strings;
+    self.update();
+    Ok(())
   }
 
-  pub fn raw_strings(&self) -> &[Vec<u8>] {
-    &self.raw_strings
+  /// Sets every string from its text and control tag segments.
+  pub fn set_segments<I, S>(&mut self, messages: I) -> Result<()>
+    where I: IntoIterator<Item = S>,
+          S: AsRef<[Segment]>,
+  {
+    let (encoding, endianness) = (self.header.encoding, self.header.endianness);
+    let raw_strings = messages.into_iter()
+      .enumerate()
+      .map(|(i, segments)| Segment::encode(segments.as_ref(), encoding, endianness)
+        .map_err(|e| Txt2::string_error(i, e)))
+      .collect::<Result<_>>()?;
+    self.txt2_or_insert().raw_strings = raw_strings;
+    self.update();
+    Ok(())
+  }
+
+  /// Renders every string as markup.
+  ///
+  /// See the [`markup`] module for the syntax.
+  pub fn markup(&self) -> Result<Vec<String>> {
+    Ok(self.segments()?.iter().map(|s| markup::render(s)).collect())
+  }
+
+  /// Sets every string from markup.
+  pub fn set_markup<I, S>(&mut self, strings: I) -> Result<()>
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str>,
+  {
+    let segments = strings.into_iter()
+      .enumerate()
+      .map(|(i, s)| markup::parse(s.as_ref()).map_err(|e| Txt2::string_error(i, e)))
+      .collect::<Result<Vec<_>>>()?;
+    self.set_segments(segments)
   }
 
-  // can't implement this even incorrectly until control sequence parsing
-  // pub fn set_raw_strings<I, S>(&mut self, strings: I) -> crate::error::Result<()>
-  //   where I: IntoIterator<Item = S>,
-  //         S: Into<Vec<u8>>,
-  // {
-  //   let _raw_strings: Vec<Vec<u8>> = strings.into_iter().map(Into::into).collect();
-  //   unimplemented!() // FIXME
-  // }
+  /// Renders every string as markup, naming the tags known to `schema`.
+  pub fn markup_with_schema(&self, schema: &TagSchema) -> Result<Vec<String>> {
+    Ok(self.segments()?
+      .iter()
+      .map(|s| markup::render_with_schema(s, schema, self.header.encoding, self.header.endianness))
+      .collect())
+  }
+
+  /// Sets every string from markup, resolving named tags with `schema`.
+  pub fn set_markup_with_schema<I, S>(&mut self, strings: I, schema: &TagSchema) -> Result<()>
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str>,
+  {
+    let (encoding, endianness) = (self.header.encoding, self.header.endianness);
+    let segments = strings.into_iter()
+      .enumerate()
+      .map(|(i, s)| markup::parse_with_schema(s.as_ref(), schema, encoding, endianness)
+        .map_err(|e| Txt2::string_error(i, e)))
+      .collect::<Result<Vec<_>>>()?;
+    self.set_segments(segments)
+  }
+
+  fn raw_strings(&self) -> &[Vec<u8>] {
+    self.txt2.as_ref().map(|t| t.raw_strings.as_slice()).unwrap_or(&[])
+  }
+
+  fn decode<'a>(&self, raw: &'a [u8]) -> Result<Cow<'a, str>> {
+    let raw = message::strip_terminator(raw, self.header.encoding);
+    match self.header.encoding {
+      Encoding::Utf8 => std::str::from_utf8(raw).map(Cow::from).map_err(Error::InvalidBorrowedUtf8),
+      encoding => message::decode_string(raw, encoding, self.header.endianness).map(Cow::from),
+    }
+  }
+
+  pub(crate) fn txt2_or_insert(&mut self) -> &mut Txt2 {
+    if self.txt2.is_none() {
+      self.section_order.push(SectionTag::Txt2);
+    }
+    self.txt2.get_or_insert_with(Txt2::empty)
+  }
 }
 
 impl CalculatesSize for Txt2 {
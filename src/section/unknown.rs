@@ -0,0 +1,84 @@
+use crate::traits::{CalculatesSize, Updates};
+use super::Section;
+
+#[cfg(feature = "serde_support")]
+use serde_derive::{Deserialize, Serialize};
+
+/// A section this crate does not understand, kept as raw bytes so it can be written back as-is.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde_support", derive(Deserialize, Serialize))]
+pub struct UnknownSection {
+  pub(crate) section: Section,
+  pub(crate) bytes: Vec<u8>,
+}
+
+impl UnknownSection {
+  pub fn new<V: Into<Vec<u8>>>(magic: [u8; 4], bytes: V) -> Self {
+    let bytes = bytes.into();
+    UnknownSection {
+      section: Section::new(magic, bytes.len() as u32),
+      bytes,
+    }
+  }
+
+  pub fn section(&self) -> &Section {
+    &self.section
+  }
+
+  pub fn magic(&self) -> [u8; 4] {
+    self.section.magic
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    &self.bytes
+  }
+
+  pub fn set_bytes<V: Into<Vec<u8>>>(&mut self, bytes: V) {
+    self.bytes = bytes.into();
+    self.update();
+  }
+}
+
+impl Updates for UnknownSection {
+  fn update(&mut self) {
+    self.section.size = self.bytes.len() as u32;
+  }
+}
+
+impl CalculatesSize for UnknownSection {
+  fn calc_size(&self) -> usize {
+    self.section.calc_size() + self.bytes.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Encoding, Msbt, SectionTag, builder::MsbtBuilder};
+
+  use byteordered::Endianness;
+
+  use std::io::Cursor;
+
+  #[test]
+  fn preserves_unknown_sections() {
+    let msbt = MsbtBuilder::new(Endianness::Big, Encoding::Utf8, Some(1))
+      .message("greeting", "Hello")
+      .unknown_section(UnknownSection::new(*b"ZZZ1", vec![1, 2, 3, 4, 5]))
+      .build()
+      .unwrap();
+    let mut bytes = Vec::new();
+    msbt.write_to(&mut bytes).unwrap();
+
+    let read = Msbt::from_reader(Cursor::new(&bytes)).unwrap();
+    assert_eq!(read.unknown_sections().len(), 1);
+    assert_eq!(read.unknown_sections()[0].magic(), *b"ZZZ1");
+    assert_eq!(read.unknown_sections()[0].bytes(), &[1, 2, 3, 4, 5]);
+    assert_eq!(read.section_order, msbt.section_order);
+    assert_eq!(read.section_order.last(), Some(&SectionTag::Unknown(0)));
+
+    let mut written = Vec::new();
+    read.write_to(&mut written).unwrap();
+    assert_eq!(written, bytes);
+  }
+}
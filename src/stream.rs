@@ -0,0 +1,253 @@
+//! A writer that streams messages to its output instead of building an [`Msbt`](crate::Msbt) first.
+
+use crate::{
+  Encoding,
+  Header,
+  PADDING_CHAR,
+  PADDING_LENGTH,
+  error::{Error, Result},
+  message,
+  section::{Section, lbl1::Label},
+  traits::CalculatesSize,
+};
+
+use byteordered::{Endian, Endianness};
+
+use std::{
+  collections::HashSet,
+  io::{Seek, SeekFrom, Write},
+};
+
+/// Writes an MSBT file one message at a time.
+///
+/// The number of messages must be known up front so that TXT2's offset table can be reserved.
+/// Message strings are written straight to the output, and only labels and string offsets are
+/// kept in memory. TXT2 is written before LBL1, since LBL1 can only be laid out once every label
+/// is known; [`finish`](MsbtStreamWriter::finish) then writes LBL1 and back-patches the TXT2
+/// offsets and size and the header's file size.
+#[derive(Debug)]
+pub struct MsbtStreamWriter<W> {
+  writer: W,
+  start: u64,
+  header: Header,
+  group_count: u32,
+  message_count: u32,
+  txt2_start: u64,
+  // the size of TXT2's string count and offset table, where the strings start
+  strings_start: u32,
+  names: HashSet<String>,
+  labels: Vec<(String, u32)>,
+  offsets: Vec<u32>,
+  strings_size: u32,
+}
+
+impl<W: Write + Seek> MsbtStreamWriter<W> {
+  /// Starts writing a file with `message_count` messages and `group_count` label hash groups at
+  /// the writer's current position.
+  pub fn new(mut writer: W, endianness: Endianness, encoding: Encoding, group_count: u32, message_count: u32) -> Result<Self> {
+    if group_count == 0 {
+      return Err(Error::InvalidGroupCount(group_count));
+    }
+    let strings_start = message_count.checked_mul(4)
+      .and_then(|size| size.checked_add(4))
+      .ok_or(Error::SectionTooLarge(*b"TXT2"))?;
+
+    let start = writer.stream_position().map_err(Error::Io)?;
+    let header = Header {
+      section_count: 2,
+      ..Header::new(endianness, encoding)
+    };
+    header.write_to(&mut writer, 0)?;
+
+    // reserve TXT2's header, string count and offset table
+    let txt2_start = writer.stream_position().map_err(Error::Io)?;
+    let reserved = Section::new(*b"TXT2", 0).calc_size() + strings_start as usize;
+    writer.write_all(&vec![0; reserved]).map_err(Error::Io)?;
+
+    Ok(MsbtStreamWriter {
+      writer,
+      start,
+      header,
+      group_count,
+      message_count,
+      txt2_start,
+      strings_start,
+      names: HashSet::new(),
+      labels: Vec::new(),
+      offsets: Vec::with_capacity(message_count as usize),
+      strings_size: 0,
+    })
+  }
+
+  pub fn header(&self) -> &Header {
+    &self.header
+  }
+
+  /// The number of messages written so far.
+  pub fn written(&self) -> u32 {
+    self.offsets.len() as u32
+  }
+
+  /// Writes a message's raw bytes, which should include its null terminator.
+  pub fn write_raw<S: Into<String>>(&mut self, label: S, raw: &[u8]) -> Result<()> {
+    let label = label.into();
+    if label.len() > u8::MAX as usize {
+      return Err(Error::LabelTooLong(label));
+    }
+    if self.offsets.len() as u32 == self.message_count {
+      return Err(Error::MessageCountMismatch {
+        expected: self.message_count,
+        actual: self.message_count + 1,
+      });
+    }
+
+    let strings_size = Some(raw.len())
+      .filter(|&len| len <= u32::MAX as usize)
+      .and_then(|len| self.strings_size.checked_add(len as u32))
+      .filter(|&size| self.strings_start.checked_add(size).is_some())
+      .ok_or(Error::SectionTooLarge(*b"TXT2"))?;
+    if !self.names.insert(label.clone()) {
+      return Err(Error::DuplicateLabel(label));
+    }
+
+    let offset = self.strings_start + self.strings_size;
+    self.writer.write_all(raw).map_err(Error::Io)?;
+    self.strings_size = strings_size;
+
+    self.labels.push((label, self.offsets.len() as u32));
+    self.offsets.push(offset);
+    Ok(())
+  }
+
+  /// Writes a message, encoding it in the file's encoding and adding a null terminator.
+  pub fn write_str<S: Into<String>>(&mut self, label: S, text: &str) -> Result<()> {
+    let raw = message::encode_message(text, self.header.encoding, self.header.endianness);
+    self.write_raw(label, &raw)
+  }
+
+  /// Writes LBL1 and fills in everything that depended on the messages, returning the writer
+  /// positioned at the end of the file.
+  pub fn finish(mut self) -> Result<W> {
+    if self.offsets.len() as u32 != self.message_count {
+      return Err(Error::MessageCountMismatch {
+        expected: self.message_count,
+        actual: self.offsets.len() as u32,
+      });
+    }
+    let endianness = self.header.endianness;
+    self.write_padding()?;
+
+    // LBL1, with labels grouped by hash
+    let group_count = self.group_count;
+    let mut groups: Vec<Vec<(String, u32)>> = vec![Vec::new(); group_count as usize];
+    let labels_size: usize = self.labels.iter().map(|(name, _)| 1 + name.len() + 4).sum();
+    for label in std::mem::take(&mut self.labels) {
+      groups[Label::hash(&label.0, group_count) as usize].push(label);
+    }
+    let table_size = 4 + group_count as usize * 8;
+    if table_size + labels_size > u32::MAX as usize {
+      return Err(Error::SectionTooLarge(*b"LBL1"));
+    }
+
+    let lbl1 = Section::new(*b"LBL1", (table_size + labels_size) as u32);
+    self.write_section(&lbl1)?;
+    endianness.write_u32(&mut self.writer, group_count).map_err(Error::Io)?;
+    let mut offset = table_size;
+    for group in &groups {
+      endianness.write_u32(&mut self.writer, group.len() as u32).map_err(Error::Io)?;
+      endianness.write_u32(&mut self.writer, offset as u32).map_err(Error::Io)?;
+      offset += group.iter().map(|(name, _)| 1 + name.len() + 4).sum::<usize>();
+    }
+    for (name, index) in groups.iter().flatten() {
+      self.writer.write_all(&[name.len() as u8]).map_err(Error::Io)?;
+      self.writer.write_all(name.as_bytes()).map_err(Error::Io)?;
+      endianness.write_u32(&mut self.writer, *index).map_err(Error::Io)?;
+    }
+    self.write_padding()?;
+    let end = self.writer.stream_position().map_err(Error::Io)?;
+
+    // back-patch TXT2
+    let txt2 = Section::new(*b"TXT2", self.strings_start + self.strings_size);
+    self.writer.seek(SeekFrom::Start(self.txt2_start)).map_err(Error::Io)?;
+    self.write_section(&txt2)?;
+    endianness.write_u32(&mut self.writer, self.message_count).map_err(Error::Io)?;
+    for &offset in &self.offsets {
+      endianness.write_u32(&mut self.writer, offset).map_err(Error::Io)?;
+    }
+
+    // back-patch the header
+    let file_size = end - self.start;
+    if file_size > u64::from(u32::MAX) {
+      return Err(Error::FileTooLarge(file_size));
+    }
+    self.writer.seek(SeekFrom::Start(self.start)).map_err(Error::Io)?;
+    self.header.file_size = file_size as u32;
+    self.header.write_to(&mut self.writer, self.header.file_size)?;
+
+    self.writer.seek(SeekFrom::Start(end)).map_err(Error::Io)?;
+    Ok(self.writer)
+  }
+
+  fn write_section(&mut self, section: &Section) -> Result<()> {
+    self.writer.write_all(&section.magic).map_err(Error::Io)?;
+    self.header.endianness.write_u32(&mut self.writer, section.size).map_err(Error::Io)?;
+    self.writer.write_all(&section.padding).map_err(Error::Io)
+  }
+
+  fn write_padding(&mut self) -> Result<()> {
+    let written = self.writer.stream_position().map_err(Error::Io)? - self.start;
+    let remainder = written as usize % PADDING_LENGTH;
+    if remainder == 0 {
+      return Ok(());
+    }
+
+    self.writer.write_all(&vec![PADDING_CHAR; PADDING_LENGTH - remainder]).map_err(Error::Io)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Msbt;
+
+  use std::io::Cursor;
+
+  #[test]
+  fn writes_readable_files() {
+    let mut writer = MsbtStreamWriter::new(Cursor::new(Vec::new()), Endianness::Big, Encoding::Utf16, 7, 3).unwrap();
+    writer.write_str("first", "One").unwrap();
+    writer.write_raw("second", &[0, 0x54, 0, 0x77, 0, 0x6f, 0, 0]).unwrap();
+    writer.write_str("third", "").unwrap();
+    let bytes = writer.finish().unwrap().into_inner();
+
+    let msbt = Msbt::from_reader(Cursor::new(&bytes)).unwrap();
+    assert_eq!(msbt.header().file_size() as usize, bytes.len());
+    assert_eq!(msbt.lbl1().unwrap().group_count(), 7);
+    assert_eq!(msbt.strings().unwrap(), vec!["One", "Two", ""]);
+    assert_eq!(msbt.get("second").unwrap().as_deref(), Some("Two"));
+    assert_eq!(msbt.label("third").map(|l| l.index()), Some(2));
+
+    let mut written = Vec::new();
+    msbt.write_to(&mut written).unwrap();
+    assert_eq!(Msbt::from_reader(Cursor::new(written)).unwrap().strings().unwrap(), msbt.strings().unwrap());
+  }
+
+  #[test]
+  fn rejects_invalid_messages() {
+    let new = |group_count, message_count| {
+      MsbtStreamWriter::new(Cursor::new(Vec::new()), Endianness::Little, Encoding::Utf8, group_count, message_count)
+    };
+    assert!(matches!(new(0, 1), Err(Error::InvalidGroupCount(0))));
+    assert!(matches!(new(1, u32::MAX), Err(Error::SectionTooLarge(magic)) if &magic == b"TXT2"));
+
+    let mut writer = new(1, 1).unwrap();
+    writer.write_str("a", "x").unwrap();
+    assert!(matches!(writer.write_str("b", "y"), Err(Error::MessageCountMismatch { expected: 1, actual: 2 })));
+
+    let mut writer = new(1, 2).unwrap();
+    writer.write_str("a", "x").unwrap();
+    assert!(matches!(writer.write_str("a", "y"), Err(Error::DuplicateLabel(ref name)) if name == "a"));
+    assert!(matches!(writer.write_str("b".repeat(256), "y"), Err(Error::LabelTooLong(_))));
+    assert!(matches!(writer.finish(), Err(Error::MessageCountMismatch { expected: 2, actual: 1 })));
+  }
+}
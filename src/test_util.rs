@@ -0,0 +1,17 @@
+//! Fixtures shared by the unit tests.
+
+use crate::{Encoding, builder::MsbtBuilder};
+
+use byteordered::Endianness;
+
+/// A little-endian UTF-16 file with the messages "greeting" and "farewell".
+pub(crate) fn sample() -> Vec<u8> {
+  let msbt = MsbtBuilder::new(Endianness::Little, Encoding::Utf16, Some(3))
+    .message("greeting", "Hello")
+    .message("farewell", "Bye")
+    .build()
+    .unwrap();
+  let mut buf = Vec::new();
+  msbt.write_to(&mut buf).unwrap();
+  buf
+}
@@ -1,43 +1,40 @@
-use crate::traits::Updates;
+use crate::{
+  Msbt,
+  traits::Updates,
+};
 
 use std::ops::{Deref, DerefMut};
 
-pub struct Updater<'a, T>
-  where T: Updates,
-{
-  inner: &'a mut T,
+/// A mutable borrow of part of an [`Msbt`] that updates the file's sizes and offsets when dropped.
+pub struct Updater<'a, T: ?Sized> {
+  msbt: &'a mut Msbt,
+  get: fn(&Msbt) -> Option<&T>,
+  get_mut: fn(&mut Msbt) -> Option<&mut T>,
 }
 
-impl<'a, T> Updater<'a, T>
-  where T: Updates,
-{
-  pub(crate) fn new(inner: &'a mut T) -> Self {
-    Updater { inner }
+impl<'a, T: ?Sized> Updater<'a, T> {
+  pub(crate) fn new(msbt: &'a mut Msbt, get: fn(&Msbt) -> Option<&T>, get_mut: fn(&mut Msbt) -> Option<&mut T>) -> Option<Self> {
+    get(msbt)?;
+    Some(Updater { msbt, get, get_mut })
   }
 }
 
-impl<'a, T> Drop for Updater<'a, T>
-  where T: Updates,
-{
+impl<'a, T: ?Sized> Drop for Updater<'a, T> {
   fn drop(&mut self) {
-    self.inner.update();
+    self.msbt.update();
   }
 }
 
-impl<'a, T> Deref for Updater<'a, T>
-  where T: Updates,
-{
+impl<'a, T: ?Sized> Deref for Updater<'a, T> {
   type Target = T;
 
   fn deref(&self) -> &Self::Target {
-    self.inner
+    (self.get)(self.msbt).expect("checked when the updater was created")
   }
 }
 
-impl<'a, T> DerefMut for Updater<'a, T>
-  where T: Updates,
-{
+impl<'a, T: ?Sized> DerefMut for Updater<'a, T> {
   fn deref_mut(&mut self) -> &mut Self::Target {
-    self.inner
+    (self.get_mut)(self.msbt).expect("checked when the updater was created")
   }
 }
@@ -0,0 +1,235 @@
+//! Structural checks that catch problems which would otherwise be written out as a corrupt file.
+
+use crate::{
+  Msbt,
+  SectionTag,
+  section::lbl1::Label,
+  traits::CalculatesSize,
+};
+
+use thiserror::Error;
+
+use std::collections::HashSet;
+
+/// A structural problem found by [`Msbt::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Diagnostic {
+  #[error("label {label} points to string {index}, but there are only {string_count} strings")]
+  LabelIndexOutOfRange {
+    label: String,
+    index: u32,
+    string_count: u32,
+  },
+  #[error("label {0} is defined more than once")]
+  DuplicateLabel(String),
+  #[error("string {0} is not referenced by any label")]
+  UnreferencedString(u32),
+  #[error("label {label} is in hash group {group}, but hashes to group {expected}")]
+  WrongHashGroup {
+    label: String,
+    group: u32,
+    expected: u32,
+  },
+  #[error("label {0} is longer than 255 bytes")]
+  LabelTooLong(String),
+  #[error("section {magic:?} has {entries} entries, but there are {string_count} strings")]
+  EntryCountMismatch {
+    magic: [u8; 4],
+    entries: u32,
+    string_count: u32,
+  },
+  #[error("section {0:?} is in the section order, but missing")]
+  MissingSection([u8; 4]),
+  #[error("section {0:?} is in the section order more than once")]
+  DuplicateSection([u8; 4]),
+  #[error("section {0:?} is not in the section order")]
+  UnorderedSection([u8; 4]),
+  #[error("the section order refers to unknown section {0}, which does not exist")]
+  NoSuchUnknownSection(usize),
+  #[error("header says there are {header} sections, but there are {actual}")]
+  SectionCountMismatch {
+    header: u16,
+    actual: u16,
+  },
+  #[error("header says the file is {header} bytes, but it is {actual}")]
+  FileSizeMismatch {
+    header: u32,
+    actual: u32,
+  },
+}
+
+impl Msbt {
+  /// Checks this file for structural problems, returning every one found.
+  pub fn validate(&self) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let string_count = self.txt2.as_ref().map(|t| t.string_count());
+
+    if let Some(lbl1) = self.lbl1.as_ref() {
+      let mut names = HashSet::with_capacity(lbl1.labels.len());
+      let mut referenced = HashSet::with_capacity(lbl1.labels.len());
+
+      for label in &lbl1.labels {
+        if !names.insert(label.name.as_str()) {
+          diagnostics.push(Diagnostic::DuplicateLabel(label.name.clone()));
+        }
+        if label.name.len() > u8::MAX as usize {
+          diagnostics.push(Diagnostic::LabelTooLong(label.name.clone()));
+        }
+        let expected = Label::hash(&label.name, lbl1.group_count);
+        if label.checksum != expected {
+          diagnostics.push(Diagnostic::WrongHashGroup {
+            label: label.name.clone(),
+            group: label.checksum,
+            expected,
+          });
+        }
+        if let Some(string_count) = string_count {
+          if label.index >= string_count {
+            diagnostics.push(Diagnostic::LabelIndexOutOfRange {
+              label: label.name.clone(),
+              index: label.index,
+              string_count,
+            });
+          }
+        }
+        referenced.insert(label.index);
+      }
+
+      if let Some(string_count) = string_count {
+        diagnostics.extend((0..string_count)
+          .filter(|i| !referenced.contains(i))
+          .map(Diagnostic::UnreferencedString));
+      }
+    }
+
+    if let Some(string_count) = string_count {
+      let counts = [
+        (*b"ATR1", self.atr1.as_ref().map(|a| a.entry_count)),
+        (*b"TSY1", self.tsy1.as_ref().map(|t| t.styles.len() as u32)),
+      ];
+      for (magic, entries) in counts.iter() {
+        if let Some(entries) = *entries {
+          if entries != string_count {
+            diagnostics.push(Diagnostic::EntryCountMismatch {
+              magic: *magic,
+              entries,
+              string_count,
+            });
+          }
+        }
+      }
+    }
+
+    diagnostics.extend(self.section_order_diagnostics());
+
+    let section_count = self.section_order.len() as u16;
+    if self.header.section_count != section_count {
+      diagnostics.push(Diagnostic::SectionCountMismatch {
+        header: self.header.section_count,
+        actual: section_count,
+      });
+    }
+    let file_size = self.calc_size() as u32;
+    if self.header.file_size != file_size {
+      diagnostics.push(Diagnostic::FileSizeMismatch {
+        header: self.header.file_size,
+        actual: file_size,
+      });
+    }
+
+    diagnostics
+  }
+
+  /// Checks that the section order lists every section exactly once, and nothing else.
+  pub(crate) fn section_order_diagnostics(&self) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let known = [
+      (SectionTag::Lbl1, self.lbl1.is_some(), *b"LBL1"),
+      (SectionTag::Nli1, self.nli1.is_some(), *b"NLI1"),
+      (SectionTag::Ato1, self.ato1.is_some(), *b"ATO1"),
+      (SectionTag::Atr1, self.atr1.is_some(), *b"ATR1"),
+      (SectionTag::Tsy1, self.tsy1.is_some(), *b"TSY1"),
+      (SectionTag::Txt2, self.txt2.is_some(), *b"TXT2"),
+    ];
+    let unknown = self.unknown_sections
+      .iter()
+      .enumerate()
+      .map(|(i, unknown)| (SectionTag::Unknown(i), true, unknown.section.magic));
+    let sections: Vec<_> = known.iter().copied().chain(unknown).collect();
+
+    for (i, tag) in self.section_order.iter().enumerate() {
+      match sections.iter().find(|(t, _, _)| t == tag) {
+        Some(&(_, false, magic)) => diagnostics.push(Diagnostic::MissingSection(magic)),
+        Some(&(_, true, magic)) if self.section_order[..i].contains(tag) => {
+          diagnostics.push(Diagnostic::DuplicateSection(magic));
+        },
+        Some(_) => {},
+        None => if let SectionTag::Unknown(index) = *tag {
+          diagnostics.push(Diagnostic::NoSuchUnknownSection(index));
+        },
+      }
+    }
+    diagnostics.extend(sections
+      .iter()
+      .filter(|(tag, present, _)| *present && !self.section_order.contains(tag))
+      .map(|&(_, _, magic)| Diagnostic::UnorderedSection(magic)));
+
+    diagnostics
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{section::tsy1::Tsy1, test_util::sample};
+
+  use std::io::Cursor;
+
+  fn read_sample() -> Msbt {
+    Msbt::from_reader(Cursor::new(sample())).unwrap()
+  }
+
+  #[test]
+  fn accepts_valid_files() {
+    assert_eq!(read_sample().validate(), Vec::new());
+  }
+
+  #[test]
+  fn finds_label_problems() {
+    let mut msbt = read_sample();
+    let lbl1 = msbt.lbl1.as_mut().unwrap();
+    let name = lbl1.labels[0].name.clone();
+    let index = lbl1.labels[0].index;
+    lbl1.labels[1].name = name.clone();
+    lbl1.labels[1].index = index;
+    lbl1.labels[1].checksum = lbl1.labels[0].checksum;
+    let diagnostics = msbt.validate();
+    assert!(diagnostics.contains(&Diagnostic::DuplicateLabel(name.clone())));
+    assert!(diagnostics.contains(&Diagnostic::UnreferencedString(1 - index)));
+
+    let mut msbt = read_sample();
+    let label = &mut msbt.lbl1.as_mut().unwrap().labels[0];
+    let expected = label.checksum;
+    label.checksum = (expected + 1) % 3;
+    label.index = 5;
+    let (name, group) = (label.name.clone(), label.checksum);
+    let diagnostics = msbt.validate();
+    assert!(diagnostics.contains(&Diagnostic::WrongHashGroup { label: name.clone(), group, expected }));
+    assert!(diagnostics.contains(&Diagnostic::LabelIndexOutOfRange { label: name, index: 5, string_count: 2 }));
+  }
+
+  #[test]
+  fn finds_count_and_size_problems() {
+    let mut msbt = read_sample();
+    let section_count = msbt.header.section_count;
+    let file_size = msbt.header.file_size;
+    msbt.tsy1 = Some(Tsy1::new_unlinked(vec![0]));
+    msbt.section_order.push(SectionTag::Tsy1);
+
+    assert_eq!(msbt.validate(), vec![
+      Diagnostic::EntryCountMismatch { magic: *b"TSY1", entries: 1, string_count: 2 },
+      Diagnostic::SectionCountMismatch { header: section_count, actual: section_count + 1 },
+      Diagnostic::FileSizeMismatch { header: file_size, actual: msbt.calc_size() as u32 },
+    ]);
+  }
+}